@@ -0,0 +1,206 @@
+// Module for the in-run advisor, the odds of what's left given what was already seen
+use crate::bag::Bag;
+use crate::ids::{ItemId, PlayerCount, SpherePos};
+use crate::loot;
+use crate::rules::ItemPools;
+use crate::unlocks::parse_item;
+use crate::{simulate_runs, PoolArgs, RunSettings};
+use anyhow::{bail, Context, Error, Result};
+use csv::Writer;
+use loot::treasuresphere::{Colors as Treasuresphere, COLORS};
+use loot::{IT_COUNT, TS_COUNT};
+use std::cmp::Reverse;
+use std::io;
+use std::str::FromStr;
+
+#[derive(clap::Args, Debug)]
+pub struct AdviseArgs {
+    /// A treasuresphere already seen, in order, repeat for more: `COLOR` or
+    /// `COLOR:ITEM,ITEM,...` with the items it dropped so far
+    #[arg(long, required = true)]
+    seen: Vec<Seen>,
+
+    /// Number of game runs simulated from what was seen
+    #[arg(short = 'n', long, default_value_t = 10000, value_parser(clap::value_parser!(u64).range(1..=200000)))]
+    run_count: u64,
+
+    /// Player count
+    #[arg(short, long, default_value = "1")]
+    player_count: PlayerCount,
+
+    #[command(flatten)]
+    pool: PoolArgs,
+
+    /// Bag file with the count of each treasuresphere color, `default` for the game's bag
+    #[arg(short, long, default_value = "default")]
+    bag: String,
+
+    /// Use a positive integer (u64) seed for RNG (non-compliant)
+    #[arg(short, long)]
+    seed: Option<u64>,
+}
+
+/// A treasuresphere seen in the run and the items it dropped so far
+#[derive(Debug, Clone)]
+pub struct Seen {
+    ts: Treasuresphere,
+    items: Vec<ItemId>,
+}
+
+impl FromStr for Seen {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let (color, list) = s.split_once(':').unwrap_or((s, ""));
+        let ts = color.trim().parse()?;
+        let items = list
+            .split(',')
+            .map(str::trim)
+            .filter(|x| !x.is_empty())
+            .map(parse_item)
+            .collect::<Result<Vec<ItemId>, Error>>()?;
+        Ok(Seen { ts, items })
+    }
+}
+
+/// Checks what was seen could happen in a game with these settings
+fn check_seen(
+    seen: &[Seen],
+    loot_counts: &[usize],
+    pools: &ItemPools,
+    bag: &Bag,
+) -> Result<(), Error> {
+    if seen.len() > *TS_COUNT {
+        bail!("Only {} treasurespheres drop in a game", TS_COUNT);
+    }
+
+    let mut items_seen: Vec<ItemId> = Vec::new();
+    for (t, s) in SpherePos::all().zip(seen) {
+        let same_color = seen[..t.index()].iter().filter(|x| x.ts == s.ts).count();
+        let bag_count = bag.spheres().iter().filter(|x| **x == s.ts).count();
+        if same_color >= bag_count {
+            bail!(
+                "ts_{}: {} can't drop more than {} time(s)",
+                t,
+                s.ts,
+                bag_count
+            );
+        }
+        if s.items.len() > loot_counts[t.index()] {
+            bail!(
+                "ts_{}: {} items seen, but only {} drop",
+                t,
+                s.items.len(),
+                loot_counts[t.index()]
+            );
+        }
+
+        let pool = pools.get(&s.ts);
+        for item in &s.items {
+            if items_seen.contains(item) {
+                bail!("ts_{}: {} was already seen", t, item);
+            }
+            if !pool.contains(item) {
+                bail!("ts_{}: {} isn't in the {} pool", t, item, s.ts);
+            }
+            if !pools.rules().is_item_in_ts_pos(item, &t) {
+                bail!("ts_{}: {} can't drop this late", t, item);
+            }
+            items_seen.push(*item);
+        }
+    }
+
+    Ok(())
+}
+
+/// Simulates the rest of the run and writes, as csv to stdout, the probability
+/// of every color and item at each treasuresphere position left
+pub fn run(args: &AdviseArgs) -> Result<(), Error> {
+    let pools = args.pool.load()?;
+    let player_count = args.player_count;
+    let game_count = args.run_count as usize;
+    let loot_counts = loot::player_loot::loot_counts(player_count);
+
+    let bag = Bag::load(&args.bag)?;
+    check_seen(&args.seen, &loot_counts, &pools, &bag).context("Invalid --seen")?;
+    let seen_ts: Vec<Treasuresphere> = args.seen.iter().map(|x| x.ts).collect();
+    let seen_it: Vec<Vec<ItemId>> = args.seen.iter().map(|x| x.items.clone()).collect();
+
+    // Counts of [position][color] and [position][item]
+    type Counts = (Vec<Vec<u64>>, Vec<Vec<u64>>);
+    let empty = || -> Counts {
+        (
+            vec![vec![0; COLORS.len()]; *TS_COUNT],
+            vec![vec![0; *IT_COUNT]; *TS_COUNT],
+        )
+    };
+
+    let settings = RunSettings {
+        bag: &bag,
+        seen_ts: &seen_ts,
+        seen_it: &seen_it,
+        ..RunSettings::new(args.seed, player_count, &pools)
+    };
+    let (ts_counts, it_counts) = simulate_runs(
+        &game_count,
+        &settings,
+        empty,
+        |mut acc, ts, it| {
+            let mut start = 0;
+            for (t, loot_count) in loot_counts.iter().enumerate() {
+                let color = COLORS.iter().position(|x| *x == ts[t]).unwrap_or(0);
+                acc.0[t][color] += 1;
+                for item in &it[start..start + loot_count] {
+                    acc.1[t][item.index()] += 1;
+                }
+                start += loot_count;
+            }
+            acc
+        },
+        |mut a, b| {
+            for (x, y) in a.0.iter_mut().flatten().zip(b.0.iter().flatten()) {
+                *x += y;
+            }
+            for (x, y) in a.1.iter_mut().flatten().zip(b.1.iter().flatten()) {
+                *x += y;
+            }
+            a
+        },
+    )?;
+
+    let mut wtr = Writer::from_writer(io::stdout());
+    wtr.write_record(["position", "kind", "name", "probability"])?;
+    let mut write_rows = |t: usize, kind: &str, rows: Vec<(String, u64)>| -> Result<(), Error> {
+        let mut rows: Vec<(String, u64)> = rows.into_iter().filter(|x| x.1 > 0).collect();
+        rows.sort_by_key(|x| Reverse(x.1));
+        for (name, count) in rows {
+            let probability = format!("{:.6}", count as f64 / game_count as f64);
+            wtr.write_record([format!("ts_{}", t), kind.to_string(), name, probability])?;
+        }
+        Ok(())
+    };
+
+    for t in 0..*TS_COUNT {
+        // Only the unknowns, colors of seen treasurespheres are certain
+        if t >= args.seen.len() {
+            let colors = COLORS
+                .iter()
+                .zip(&ts_counts[t])
+                .map(|(c, n)| (c.to_string(), *n))
+                .collect();
+            write_rows(t, "ts", colors)?;
+        }
+        let seen_count = args.seen.get(t).map_or(0, |x| x.items.len());
+        if seen_count < loot_counts[t] {
+            let items = ItemId::all()
+                .zip(&it_counts[t])
+                .filter(|(item, _)| !args.seen.get(t).is_some_and(|x| x.items.contains(item)))
+                .map(|(item, n)| (item.to_string(), *n))
+                .collect();
+            write_rows(t, "item", items)?;
+        }
+    }
+
+    wtr.flush()?;
+    Ok(())
+}
@@ -0,0 +1,124 @@
+// Module for the bag of treasurespheres a game draws from, i.e. the sphere weights
+use crate::loot;
+use anyhow::{bail, Context, Error, Result};
+use loot::treasuresphere::{Colors as Treasuresphere, BAG, COLORS};
+use loot::TS_COUNT;
+use std::fs;
+use std::sync::LazyLock;
+
+/// Most treasurespheres a bag can hold, so a game shuffles it on the stack
+pub const BAG_MAX: usize = 16;
+
+static STANDARD: LazyLock<Bag> = LazyLock::new(|| Bag {
+    spheres: BAG.to_vec(),
+});
+
+/// Treasurespheres a game draws from without replacement
+///
+/// Bag files give the count of each color, unlisted colors aren't in the bag.
+/// Blank lines and anything after `#` are ignored:
+/// ```text
+/// normal 2    # one Normal less than the game
+/// opal 2
+/// sapphire 1
+/// ruby 1
+/// garnet 1
+/// emerald 1
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bag {
+    spheres: Vec<Treasuresphere>, // in COLORS order
+}
+
+impl Bag {
+    /// The game's bag of 8, Normal 3 times
+    pub fn standard() -> &'static Bag {
+        &STANDARD
+    }
+
+    /// Loads the game's bag for `default`, otherwise treats `name` as a file path
+    pub fn load(name: &str) -> Result<Self, Error> {
+        match name {
+            "default" => Ok(Bag::standard().clone()),
+            path => Bag::from_file(path),
+        }
+    }
+
+    /// Reads a bag file, see [`Bag`] for the format
+    pub fn from_file(path: &str) -> Result<Self, Error> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Could not read bag file '{}'", path))?;
+        Bag::parse(&text).with_context(|| format!("Invalid bag file '{}'", path))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut counts = [0; COLORS.len()];
+        for (line_no, line) in text.lines().enumerate() {
+            let entry = line.split('#').next().unwrap_or("").trim();
+            if entry.is_empty() {
+                continue;
+            }
+            let parsed: Result<(Treasuresphere, usize), Error> =
+                (|| match entry.split_whitespace().collect::<Vec<&str>>()[..] {
+                    [color, count] => Ok((color.parse()?, count.parse()?)),
+                    _ => bail!("expected a treasuresphere and a count"),
+                })();
+            let (color, count) = parsed.with_context(|| format!("line {}", line_no + 1))?;
+            counts[color as usize] += count;
+        }
+
+        let spheres: Vec<Treasuresphere> = COLORS
+            .iter()
+            .zip(counts)
+            .flat_map(|(color, count)| std::iter::repeat_n(*color, count))
+            .collect();
+        if !(*TS_COUNT..=BAG_MAX).contains(&spheres.len()) {
+            bail!(
+                "{} treasurespheres, a bag holds {} to {}",
+                spheres.len(),
+                *TS_COUNT,
+                BAG_MAX
+            );
+        }
+        Ok(Bag { spheres })
+    }
+
+    pub fn spheres(&self) -> &[Treasuresphere] {
+        &self.spheres
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_counts_every_color() {
+        let bag = Bag::parse("# the game's bag\nnormal 3\nopal 1\nsapphire 1\n\nruby 1\ngarnet 1\nemerald 1  # last\n")
+            .unwrap();
+        assert_eq!(&bag, Bag::standard());
+
+        let bag = Bag::parse("emerald 2\nnormal 4\n").unwrap();
+        assert_eq!(
+            bag.spheres(),
+            [
+                [Treasuresphere::Normal; 4].as_slice(),
+                &[Treasuresphere::Emerald; 2]
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn parse_rejects_bad_lines_and_sizes() {
+        let error = Bag::parse("normal 6\nopal\n").unwrap_err();
+        assert_eq!(
+            format!("{:#}", error),
+            "line 2: expected a treasuresphere and a count"
+        );
+        assert!(Bag::parse("amber 6\n").is_err());
+        assert!(Bag::parse("normal many\n").is_err());
+        assert!(Bag::parse("normal 5\n").is_err());
+        assert!(Bag::parse(&format!("normal {}\n", BAG_MAX + 1)).is_err());
+    }
+}
@@ -0,0 +1,385 @@
+// Module for the packed binary run format
+//
+// A 16 byte header, then fixed-size records of 37 bytes until the end of the file:
+// header: b"RNSB", format version (u8), flags (u8, bit 0: seeded), reserved (u8, 0),
+//         player count (u8, 0 if mixed), seed (u64, little endian, 0 if not seeded)
+// record: player count (u8), 6 treasurespheres (u8, see COLOR_CODES),
+//         6 x 5 item slots (u8, item index or 255 if empty)
+use crate::compress;
+use crate::error::SimError;
+use crate::ids::{ItemId, PlayerCount};
+use crate::loot;
+use anyhow::{bail, Context, Error, Result};
+use loot::treasuresphere::Colors as Treasuresphere;
+use loot::{IT_FOUND_MAX_N, TS_N};
+use std::io::{ErrorKind, Read, Write};
+
+const MAGIC: &[u8; 4] = b"RNSB";
+pub const FORMAT_VERSION: u8 = 1;
+pub const HEADER_SIZE: usize = 16;
+pub const RECORD_SIZE: usize = 1 + TS_N + TS_N * IT_FOUND_MAX_N;
+const EMPTY_SLOT: u8 = u8::MAX;
+
+/// Codes of the treasurespheres in a record, read and written through this table only
+const COLOR_CODES: &[(Treasuresphere, u8)] = &[
+    (Treasuresphere::Normal, 0),
+    (Treasuresphere::Opal, 1),
+    (Treasuresphere::Sapphire, 2),
+    (Treasuresphere::Ruby, 3),
+    (Treasuresphere::Garnet, 4),
+    (Treasuresphere::Emerald, 5),
+];
+
+fn to_code<T: PartialEq + std::fmt::Display>(table: &[(T, u8)], value: &T) -> Result<u8, SimError> {
+    table
+        .iter()
+        .find(|(x, _)| x == value)
+        .map(|(_, code)| *code)
+        .ok_or_else(|| SimError::NoBinaryCode(value.to_string()))
+}
+
+fn from_code<T: Copy>(table: &[(T, u8)], code: u8) -> Option<T> {
+    table
+        .iter()
+        .find(|(_, x)| *x == code)
+        .map(|(value, _)| *value)
+}
+
+/// Settings shared by every run in a file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Header {
+    pub version: u8,
+    pub seed: Option<u64>,
+    pub player_count: Option<PlayerCount>, // none if the player counts are mixed
+}
+
+impl Header {
+    pub fn new(seed: Option<u64>, player_count: Option<PlayerCount>) -> Self {
+        Header {
+            version: FORMAT_VERSION,
+            seed,
+            player_count,
+        }
+    }
+
+    pub fn to_bytes(self) -> [u8; HEADER_SIZE] {
+        let mut bytes = [0; HEADER_SIZE];
+        bytes[..4].copy_from_slice(MAGIC);
+        bytes[4] = self.version;
+        bytes[5] = self.seed.is_some() as u8;
+        bytes[7] = self.player_count.map_or(0, |p| p.get() as u8);
+        bytes[8..].copy_from_slice(&self.seed.unwrap_or(0).to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; HEADER_SIZE]) -> Result<Self, Error> {
+        if &bytes[..4] != MAGIC {
+            bail!("Not a binary run file");
+        }
+        if bytes[4] != FORMAT_VERSION {
+            bail!("Unsupported binary format version {}", bytes[4]);
+        }
+        let player_count = match bytes[7] {
+            0 => None,
+            x => Some(PlayerCount::new(x as usize)?),
+        };
+        let mut seed = [0; 8];
+        seed.copy_from_slice(&bytes[8..]);
+        Ok(Header {
+            version: bytes[4],
+            seed: (bytes[5] & 1 == 1).then_some(u64::from_le_bytes(seed)),
+            player_count,
+        })
+    }
+}
+
+/// A run as stored in a record
+#[derive(Debug, Clone, PartialEq)]
+pub struct Run {
+    pub player_count: PlayerCount,
+    pub ts: Vec<Treasuresphere>,
+    pub items: Vec<Vec<ItemId>>, // [position][index]
+}
+
+impl Run {
+    /// Items of every treasuresphere in order, the way the generator returns them
+    pub fn flat_items(&self) -> Vec<ItemId> {
+        self.items.iter().flatten().copied().collect()
+    }
+}
+
+/// Appends a run as a record, `loot_counts` splits the items per treasuresphere
+pub fn encode_run(
+    out: &mut Vec<u8>,
+    player_count: &PlayerCount,
+    ts: &[Treasuresphere],
+    items: &[ItemId],
+    loot_counts: &[usize],
+) -> Result<(), SimError> {
+    let mut record = [EMPTY_SLOT; RECORD_SIZE];
+    record[0] = player_count.get() as u8;
+    for (t, color) in ts.iter().take(TS_N).enumerate() {
+        record[1 + t] = to_code(COLOR_CODES, color)?;
+    }
+    let mut start = 0;
+    for (t, loot_count) in loot_counts.iter().take(TS_N).enumerate() {
+        for (i, item) in items[start..start + loot_count].iter().enumerate() {
+            record[1 + TS_N + t * IT_FOUND_MAX_N + i] = item.as_u8();
+        }
+        start += loot_count;
+    }
+    out.extend_from_slice(&record);
+    Ok(())
+}
+
+/// Reads a record back, its item counts have to match its player count
+pub fn decode_run(record: &[u8; RECORD_SIZE]) -> Result<Run, Error> {
+    let player_count = PlayerCount::new(record[0] as usize)?;
+    let ts = record[1..=TS_N]
+        .iter()
+        .map(|x| match from_code(COLOR_CODES, *x) {
+            Some(color) => Ok(color),
+            None => bail!("Invalid treasuresphere code {}", x),
+        })
+        .collect::<Result<_, Error>>()?;
+    let loot_counts = loot::player_loot::loot_counts(player_count);
+    let items = record[1 + TS_N..]
+        .chunks(IT_FOUND_MAX_N)
+        .zip(&loot_counts)
+        .enumerate()
+        .map(|(t, (slots, loot_count))| {
+            let items = slots
+                .iter()
+                .filter(|x| **x != EMPTY_SLOT)
+                .map(|x| Ok(ItemId::new(*x as usize)?))
+                .collect::<Result<Vec<ItemId>, Error>>()?;
+            if items.len() != *loot_count {
+                bail!(
+                    "{} items in treasuresphere {}, but {} drop for {} player(s)",
+                    items.len(),
+                    t,
+                    loot_count,
+                    player_count
+                );
+            }
+            Ok(items)
+        })
+        .collect::<Result<_, Error>>()?;
+    Ok(Run {
+        player_count,
+        ts,
+        items,
+    })
+}
+
+/// Reads the header, then the runs one record at a time
+pub struct BinaryReader<R: Read> {
+    input: R,
+    header: Header,
+    record: usize,
+}
+
+impl BinaryReader<Box<dyn Read>> {
+    /// Opens a binary run file, compressed or not
+    pub fn open(path: &str) -> Result<Self, Error> {
+        BinaryReader::new(compress::open(path)?).with_context(|| format!("'{}'", path))
+    }
+}
+
+impl<R: Read> BinaryReader<R> {
+    pub fn new(mut input: R) -> Result<Self, Error> {
+        let mut bytes = [0; HEADER_SIZE];
+        input
+            .read_exact(&mut bytes)
+            .context("File too short for a header")?;
+        Ok(BinaryReader {
+            input,
+            header: Header::from_bytes(&bytes)?,
+            record: 0,
+        })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+}
+
+impl<R: Read> Iterator for BinaryReader<R> {
+    type Item = Result<Run, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut record = [0; RECORD_SIZE];
+        let mut filled = 0;
+        while filled < RECORD_SIZE {
+            match self.input.read(&mut record[filled..]) {
+                Ok(0) if filled == 0 => return None,
+                Ok(0) => return Some(Err(anyhow::anyhow!("Truncated record {}", self.record))),
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+        self.record += 1;
+        Some(decode_run(&record).with_context(|| format!("record {}", self.record - 1)))
+    }
+}
+
+/// Writes the header, then records encoded with `encode_run`
+pub fn write_header(out: &mut impl Write, header: &Header) -> Result<(), Error> {
+    out.write_all(&header.to_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::{ItemPools, Rules};
+    use crate::unlocks::Unlocks;
+    use crate::{generate_it, generate_ts, run_rng};
+    use loot::player_loot::loot_counts;
+    use loot::treasuresphere::COLORS;
+
+    /// A file of `count` runs, the i-th one played by `player_counts[i % len]`
+    fn file(header: &Header, player_counts: &[usize], count: usize) -> (Vec<u8>, Vec<Run>) {
+        let pools = ItemPools::new(&Unlocks::all(), Rules::default());
+        let mut bytes = header.to_bytes().to_vec();
+        let mut runs = Vec::new();
+        for i in 0..count {
+            let player_count = PlayerCount::new(player_counts[i % player_counts.len()]).unwrap();
+            let loot_counts = loot_counts(player_count);
+            let mut rng = run_rng(&Some(9), &i);
+            let ts = generate_ts(&mut rng);
+            let items = generate_it(&ts, &mut rng, &player_count, &pools).unwrap();
+            encode_run(&mut bytes, &player_count, &ts, &items, &loot_counts).unwrap();
+
+            let mut start = 0;
+            let items = loot_counts
+                .iter()
+                .map(|count| {
+                    start += count;
+                    items[start - count..start].to_vec()
+                })
+                .collect();
+            runs.push(Run {
+                player_count,
+                ts,
+                items,
+            });
+        }
+        (bytes, runs)
+    }
+
+    fn read(bytes: &[u8]) -> Result<(Header, Vec<Run>), Error> {
+        let reader = BinaryReader::new(bytes)?;
+        let header = *reader.header();
+        Ok((header, reader.collect::<Result<_, Error>>()?))
+    }
+
+    #[test]
+    fn every_code_is_distinct_and_covers_its_type() {
+        for color in COLORS.iter() {
+            assert_eq!(
+                from_code(COLOR_CODES, to_code(COLOR_CODES, color).unwrap()),
+                Some(*color)
+            );
+        }
+        assert_eq!(from_code(COLOR_CODES, EMPTY_SLOT), None);
+        assert_eq!(
+            to_code(&COLOR_CODES[1..], &Treasuresphere::Normal)
+                .unwrap_err()
+                .to_string(),
+            "normal has no binary code"
+        );
+    }
+
+    #[test]
+    fn runs_round_trip() {
+        let header = Header::new(Some(9), PlayerCount::new(3).ok());
+        let (bytes, runs) = file(&header, &[3], 20);
+        assert_eq!(bytes.len(), HEADER_SIZE + 20 * RECORD_SIZE);
+        assert_eq!(read(&bytes).unwrap(), (header, runs));
+    }
+
+    #[test]
+    fn mixed_player_counts_round_trip() {
+        let header = Header::new(None, None);
+        assert_eq!(header.to_bytes()[7], 0);
+        let (bytes, runs) = file(&header, &[1, 4, 2], 9);
+        let (read_header, read_runs) = read(&bytes).unwrap();
+        assert_eq!(read_header.player_count, None);
+        assert_eq!(read_header.seed, None);
+        assert_eq!(read_runs, runs);
+        assert_eq!(
+            read_runs[1].flat_items().len(),
+            read_runs[1].items.concat().len()
+        );
+    }
+
+    #[test]
+    fn truncated_records_are_errors() {
+        let header = Header::new(Some(9), PlayerCount::new(1).ok());
+        let (bytes, _) = file(&header, &[1], 2);
+        let mut reader = BinaryReader::new(&bytes[..bytes.len() - 3]).unwrap();
+        assert!(reader.next().unwrap().is_ok());
+        let error = reader.next().unwrap().unwrap_err();
+        assert_eq!(error.to_string(), "Truncated record 1");
+
+        assert!(BinaryReader::new(&bytes[..HEADER_SIZE - 1]).is_err());
+    }
+
+    #[test]
+    fn bad_headers_are_errors() {
+        let header = Header::new(Some(9), None).to_bytes();
+        let error = |bytes: [u8; HEADER_SIZE]| -> String {
+            match BinaryReader::new(&bytes[..]) {
+                Ok(_) => String::new(),
+                Err(e) => e.to_string(),
+            }
+        };
+
+        let mut magic = header;
+        magic[0] = b'X';
+        assert_eq!(error(magic), "Not a binary run file");
+        let mut version = header;
+        version[4] = FORMAT_VERSION + 1;
+        assert_eq!(
+            error(version),
+            format!("Unsupported binary format version {}", FORMAT_VERSION + 1)
+        );
+        let mut player_count = header;
+        player_count[7] = 5;
+        assert!(BinaryReader::new(&player_count[..]).is_err());
+    }
+
+    #[test]
+    fn bad_codes_in_a_record_are_errors() {
+        let header = Header::new(Some(9), PlayerCount::new(1).ok());
+        let (mut bytes, _) = file(&header, &[1], 1);
+        bytes[HEADER_SIZE + 1] = 6;
+        let error = read(&bytes).unwrap_err();
+        assert_eq!(
+            format!("{:#}", error),
+            "record 0: Invalid treasuresphere code 6"
+        );
+    }
+
+    #[test]
+    fn item_counts_have_to_match_the_player_count() {
+        // 1 player finds 3 items from the third treasuresphere on, 4 players find 5
+        let header = Header::new(Some(9), None);
+        let (bytes, _) = file(&header, &[1], 1);
+        let mut four_players = bytes.clone();
+        four_players[HEADER_SIZE] = 4;
+        assert_eq!(
+            format!("{:#}", read(&four_players).unwrap_err()),
+            "record 0: 3 items in treasuresphere 2, but 5 drop for 4 player(s)"
+        );
+
+        let mut missing = bytes.clone();
+        missing[HEADER_SIZE + 1 + TS_N] = EMPTY_SLOT;
+        assert_eq!(
+            format!("{:#}", read(&missing).unwrap_err()),
+            "record 0: 4 items in treasuresphere 0, but 5 drop for 1 player(s)"
+        );
+    }
+}
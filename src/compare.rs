@@ -0,0 +1,335 @@
+// Module for comparing runs observed in the game against the simulator
+use crate::ids::{ItemId, PlayerCount, SpherePos};
+use crate::loot;
+use crate::observed::{self, ObservedRun};
+use crate::stats::{self, RunStats};
+use crate::{PoolArgs, RunSettings};
+use anyhow::{bail, Error, Result};
+use csv::Writer;
+use loot::treasuresphere::COLORS;
+use loot::{IT_COUNT, TS_COUNT};
+use std::io;
+
+#[derive(clap::Args, Debug)]
+pub struct CompareArgs {
+    /// Observed runs, csv in this program's layout or json (see the README)
+    #[arg(required = true)]
+    files: Vec<String>,
+
+    /// Number of game runs simulated per player count
+    #[arg(short = 'n', long, default_value_t = 50000, value_parser(clap::value_parser!(u64).range(1..=200000)))]
+    run_count: u64,
+
+    /// Significance level, Bonferroni corrected over the tests of a group
+    #[arg(short, long, default_value_t = 0.05)]
+    alpha: f64,
+
+    #[command(flatten)]
+    pool: PoolArgs,
+
+    /// Use a positive integer (u64) seed for RNG (non-compliant)
+    #[arg(short, long)]
+    seed: Option<u64>,
+}
+
+/// Result of one goodness-of-fit test
+struct Test {
+    name: String,
+    statistic: f64,
+    df: Option<usize>,
+    p_value: f64,
+}
+
+/// Compares every group of observed runs with the same player count
+/// against simulated runs, and prints the tests with the significant ones flagged
+pub fn run(args: &CompareArgs) -> Result<(), Error> {
+    let mut runs: Vec<ObservedRun> = Vec::new();
+    for path in &args.files {
+        runs.extend(observed::read(path)?);
+    }
+    if runs.is_empty() {
+        bail!("No observed runs to compare");
+    }
+    let pools = args.pool.load()?;
+
+    let mut groups: Vec<PlayerCount> = runs.iter().map(|x| x.player_count).collect();
+    groups.sort_unstable();
+    groups.dedup();
+
+    let mut wtr = Writer::from_writer(io::stdout());
+    wtr.write_record([
+        "player_count",
+        "runs",
+        "test",
+        "statistic",
+        "df",
+        "p_value",
+        "flagged",
+    ])?;
+
+    let mut flagged_count = 0;
+    for player_count in groups {
+        // Runs that got to the last treasuresphere, for the drop positions
+        let mut observed = RunStats::default();
+        let mut complete = RunStats::default();
+        for run in runs.iter().filter(|x| x.player_count == player_count) {
+            observed.add_observed(run);
+            if run.spheres.len() >= *TS_COUNT {
+                complete.add_observed(run);
+            }
+        }
+        let expected = stats::simulate(
+            &(args.run_count as usize),
+            &RunSettings::new(args.seed, player_count, &pools),
+        )?;
+
+        // Every frequency test, then only the items off by a significant amount, all
+        // corrected together
+        let tests = frequency_tests(&observed, &expected);
+        let mut items = item_tests(&observed, &expected);
+        items.extend(position_tests(&complete, &expected));
+        let alpha = args.alpha / (tests.len() + items.len()).max(1) as f64;
+        let rows = tests.iter().map(|x| (x, x.p_value < alpha)).chain(
+            items
+                .iter()
+                .map(|x| (x, x.p_value < alpha))
+                .filter(|(_, flagged)| *flagged),
+        );
+        for (test, flagged) in rows {
+            flagged_count += flagged as usize;
+            wtr.write_record([
+                player_count.to_string(),
+                observed.runs.to_string(),
+                test.name.clone(),
+                format!("{:.4}", test.statistic),
+                test.df.map_or(String::new(), |x| x.to_string()),
+                format!("{:.6}", test.p_value),
+                flagged.to_string(),
+            ])?;
+        }
+    }
+    wtr.flush()?;
+
+    eprintln!("{} significant deviation(s)", flagged_count);
+    Ok(())
+}
+
+/// Chi-square of the colors per position, and of the items per position and color
+///
+/// The items of a treasuresphere all come from its color's pool, so they're only
+/// compared against runs that got the same color at the same position.
+fn frequency_tests(observed: &RunStats, expected: &RunStats) -> Vec<Test> {
+    let mut tests = Vec::new();
+    for t in SpherePos::all() {
+        let reached = observed.reached(&t);
+        let total = expected.reached(&t);
+        if reached > 0 && total > 0 {
+            let bins: Vec<(u64, f64)> = (0..COLORS.len())
+                .map(|c| {
+                    let share = expected.ts_counts[t.index()][c] as f64 / total as f64;
+                    (observed.ts_counts[t.index()][c], share * reached as f64)
+                })
+                .collect();
+            tests.extend(chi_square(format!("chi2_ts_{}", t), &bins));
+        }
+    }
+
+    for t in 0..*TS_COUNT {
+        for (c, color) in COLORS.iter().enumerate() {
+            let found: u64 = observed.it_color_counts[t][c].iter().sum();
+            let total: u64 = expected.it_color_counts[t][c].iter().sum();
+            if found == 0 || total == 0 {
+                continue;
+            }
+            let bins: Vec<(u64, f64)> = (0..*IT_COUNT)
+                .map(|i| {
+                    let share = expected.it_color_counts[t][c][i] as f64 / total as f64;
+                    (observed.it_color_counts[t][c][i], share * found as f64)
+                })
+                .collect();
+            tests.extend(chi_square(format!("chi2_it_{}_{}", t, color), &bins));
+        }
+    }
+    tests
+}
+
+/// Pearson's chi-square, bins expected less than 5 times are pooled together
+fn chi_square(name: String, bins: &[(u64, f64)]) -> Option<Test> {
+    let mut kept: Vec<(f64, f64)> = Vec::new();
+    let mut pooled = (0.0, 0.0);
+    for (observed, expected) in bins {
+        if *expected < 5.0 {
+            pooled.0 += *observed as f64;
+            pooled.1 += expected;
+        } else {
+            kept.push((*observed as f64, *expected));
+        }
+    }
+    if pooled.1 >= 5.0 || kept.is_empty() {
+        kept.push(pooled);
+    } else if let Some(smallest) = kept.iter_mut().min_by(|a, b| a.1.total_cmp(&b.1)) {
+        smallest.0 += pooled.0;
+        smallest.1 += pooled.1;
+    }
+    if kept.len() < 2 {
+        return None;
+    }
+
+    let statistic: f64 = kept
+        .iter()
+        .map(|(o, e)| match e {
+            0.0 if *o == 0.0 => 0.0,
+            0.0 => f64::INFINITY, // seen but never simulated
+            e => (o - e) * (o - e) / e,
+        })
+        .sum();
+    let df = kept.len() - 1;
+    Some(Test {
+        name,
+        statistic,
+        df: Some(df),
+        p_value: stats::chi_square_p(statistic, df),
+    })
+}
+
+/// Z-score of the runs each item was found in against the runs it's expected in
+///
+/// Each run finds an item at most once, so runs are independent trials, with a chance
+/// that depends on how many treasurespheres the run got to. Like the chi-square bins,
+/// items expected in fewer than 5 runs aren't scored, unless they were never simulated.
+fn item_tests(observed: &RunStats, expected: &RunStats) -> Vec<Test> {
+    // Runs that stopped after exactly k treasurespheres
+    let reached: Vec<u64> = std::iter::once(observed.runs)
+        .chain(SpherePos::all().map(|t| observed.reached(&t)))
+        .chain(std::iter::once(0))
+        .collect();
+    let stopped: Vec<u64> = reached
+        .windows(2)
+        .map(|x| x[0].saturating_sub(x[1]))
+        .collect();
+
+    ItemId::all()
+        .filter_map(|i| {
+            // Chance of finding it by the k-th treasuresphere, 0 before the first
+            let shares = std::iter::once(0.0).chain(SpherePos::all().scan(0.0, |share, t| {
+                *share += expected.item_probability_at(&i, &t);
+                Some(*share)
+            }));
+            let mut mean = 0.0;
+            let mut variance = 0.0;
+            for (share, runs) in shares.zip(&stopped) {
+                mean += share * *runs as f64;
+                variance += share * (1.0 - share) * *runs as f64;
+            }
+            let count = observed.item_found(&i) as f64;
+            let statistic = match variance {
+                0.0 if count == mean => return None,
+                0.0 => f64::INFINITY.copysign(count - mean),
+                _ if mean < 5.0 => return None,
+                v => (count - mean) / v.sqrt(),
+            };
+            Some(Test {
+                name: format!("z_{}", i),
+                statistic,
+                df: None,
+                p_value: stats::normal_p(statistic),
+            })
+        })
+        .collect()
+}
+
+/// Kolmogorov-Smirnov of the position each item drops at, over complete runs
+fn position_tests(complete: &RunStats, expected: &RunStats) -> Vec<Test> {
+    ItemId::all()
+        .filter_map(|i| {
+            let found = complete.item_found(&i);
+            let total = expected.item_found(&i);
+            if found == 0 || total == 0 {
+                return None;
+            }
+            let mut observed_cdf = 0.0;
+            let mut expected_cdf = 0.0;
+            let mut distance: f64 = 0.0;
+            for t in 0..*TS_COUNT {
+                observed_cdf += complete.it_counts[i.index()][t] as f64 / found as f64;
+                expected_cdf += expected.it_counts[i.index()][t] as f64 / total as f64;
+                distance = distance.max((observed_cdf - expected_cdf).abs());
+            }
+            Some(Test {
+                name: format!("ks_position_{}", i),
+                statistic: distance,
+                df: None,
+                p_value: stats::ks_p(distance, found),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(index: usize) -> ItemId {
+        ItemId::new(index).unwrap()
+    }
+
+    /// `runs` runs that all got to the last treasuresphere, the items found in the first
+    fn stats(runs: u64, found: &[(usize, u64)]) -> RunStats {
+        let mut stats = RunStats {
+            runs,
+            ..RunStats::default()
+        };
+        for t in 0..*TS_COUNT {
+            stats.ts_counts[t][0] = runs;
+        }
+        for (i, count) in found {
+            stats.it_counts[*i][0] = *count;
+        }
+        stats
+    }
+
+    #[test]
+    fn chi_square_pools_rare_bins() {
+        let test = chi_square(
+            "x".to_string(),
+            &[(10, 10.0), (12, 10.0), (1, 2.0), (0, 2.0)],
+        )
+        .unwrap();
+        // The rare bins, 1 against 4, go into the first smallest bin
+        assert_eq!(test.df, Some(1));
+        assert!((test.statistic - (9.0 / 14.0 + 4.0 / 10.0)).abs() < 1e-12);
+
+        assert!(chi_square("x".to_string(), &[(3, 3.0), (1, 1.0)]).is_none());
+        // Items never simulated still count, in the bin they're pooled into
+        let unexpected = chi_square("x".to_string(), &[(10, 10.0), (10, 10.0), (6, 0.0)]).unwrap();
+        assert_eq!(unexpected.df, Some(1));
+        assert!((unexpected.statistic - 3.6).abs() < 1e-12);
+    }
+
+    #[test]
+    fn items_expected_in_fewer_than_5_runs_are_not_scored() {
+        // Expected in 18 of 20 runs (variance 1.8), in 0.6 of them, and never
+        let expected = stats(100, &[(0, 90), (1, 3)]);
+        let observed = stats(20, &[(0, 20), (2, 1)]);
+        let tests = item_tests(&observed, &expected);
+        let names: Vec<&str> = tests.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![format!("z_{}", item(0)), format!("z_{}", item(2))]
+        );
+        assert!((tests[0].statistic - 2.0 / 1.8f64.sqrt()).abs() < 1e-12);
+        assert_eq!(tests[1].statistic, f64::INFINITY);
+    }
+
+    #[test]
+    fn matching_runs_are_not_flagged() {
+        let runs = stats(50, &[(0, 40), (1, 25), (2, 10)]);
+        for test in item_tests(&runs, &runs)
+            .iter()
+            .chain(&position_tests(&runs, &runs))
+        {
+            assert_eq!(test.statistic, 0.0, "{}", test.name);
+            assert_eq!(test.p_value, 1.0, "{}", test.name);
+        }
+    }
+}
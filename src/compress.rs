@@ -0,0 +1,214 @@
+// Module for reading and writing gzip or zstd compressed files
+use anyhow::{Context, Error, Result};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Compression picked by the file extension, `.gz` or `.zst`
+    pub fn from_path(path: &str) -> Self {
+        if path.ends_with(".gz") {
+            Compression::Gzip
+        } else if path.ends_with(".zst") {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+impl std::fmt::Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Compression::None => "none",
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Path without the compression extension, i.e. runs.json.gz -> runs.json
+pub fn strip_extension(path: &str) -> &str {
+    path.strip_suffix(".gz")
+        .or_else(|| path.strip_suffix(".zst"))
+        .unwrap_or(path)
+}
+
+type Sink = BufWriter<Box<dyn Write>>;
+
+/// A buffered output, compressed or not, that has to be finished once written
+pub enum Output {
+    Plain(Sink),
+    Gzip(GzEncoder<Sink>),
+    Zstd(zstd::Encoder<'static, Sink>),
+}
+
+impl Output {
+    /// Writes what's left of the compressed stream and flushes it
+    pub fn finish(self) -> Result<(), Error> {
+        let mut sink = match self {
+            Output::Plain(sink) => sink,
+            Output::Gzip(encoder) => encoder.finish()?,
+            Output::Zstd(encoder) => encoder.finish()?,
+        };
+        sink.flush()?;
+        Ok(())
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Plain(sink) => sink.write(buf),
+            Output::Gzip(encoder) => encoder.write(buf),
+            Output::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Plain(sink) => sink.flush(),
+            Output::Gzip(encoder) => encoder.flush(),
+            Output::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Creates the output file, or stdout if there's none. Without `compress` the file
+/// extension picks the compression, stdout isn't compressed.
+pub fn create(path: &Option<String>, compress: Option<Compression>) -> Result<Output, Error> {
+    let (inner, by_path): (Box<dyn Write>, Compression) = match path {
+        Some(path) => (
+            Box::new(File::create(path).with_context(|| format!("Could not create '{}'", path))?),
+            Compression::from_path(path),
+        ),
+        None => (Box::new(io::stdout().lock()), Compression::None),
+    };
+    let sink = BufWriter::new(inner);
+    Ok(match compress.unwrap_or(by_path) {
+        Compression::None => Output::Plain(sink),
+        Compression::Gzip => Output::Gzip(GzEncoder::new(sink, flate2::Compression::default())),
+        Compression::Zstd => Output::Zstd(zstd::Encoder::new(sink, 0)?),
+    })
+}
+
+/// Opens a file for reading, decompressing it if it starts like gzip or zstd
+pub fn open(path: &str) -> Result<Box<dyn Read>, Error> {
+    let file = File::open(path).with_context(|| format!("Could not open '{}'", path))?;
+    let mut input = BufReader::new(file);
+    let start = input.fill_buf()?;
+    Ok(if start.starts_with(GZIP_MAGIC) {
+        Box::new(BufReader::new(MultiGzDecoder::new(input)))
+    } else if start.starts_with(ZSTD_MAGIC) {
+        Box::new(BufReader::new(zstd::Decoder::with_buffer(input)?))
+    } else {
+        Box::new(input)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    const TEXT: &str = "player_count,ts_0\n1,normal\n2,opal\n";
+
+    fn temp_path(name: &str, extension: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "rns-loot-sim-{}-{}{}",
+            name,
+            std::process::id(),
+            extension
+        ))
+    }
+
+    /// Writes TEXT through `create`, returns the bytes on disk and what `open` reads back
+    fn round_trip(path: &PathBuf, compress: Option<Compression>) -> (Vec<u8>, String) {
+        let path_name = path.to_str().unwrap().to_string();
+        let mut out = create(&Some(path_name.clone()), compress).unwrap();
+        out.write_all(TEXT.as_bytes()).unwrap();
+        out.finish().unwrap();
+
+        let mut text = String::new();
+        open(&path_name).unwrap().read_to_string(&mut text).unwrap();
+        let bytes = fs::read(path).unwrap();
+        fs::remove_file(path).unwrap();
+        (bytes, text)
+    }
+
+    #[test]
+    fn extensions_pick_the_compression() {
+        assert_eq!(Compression::from_path("runs.csv.gz"), Compression::Gzip);
+        assert_eq!(Compression::from_path("runs.bin.zst"), Compression::Zstd);
+        assert_eq!(Compression::from_path("runs.csv"), Compression::None);
+        assert_eq!(strip_extension("runs.json.gz"), "runs.json");
+        assert_eq!(strip_extension("runs.json.zst"), "runs.json");
+        assert_eq!(strip_extension("runs.json"), "runs.json");
+    }
+
+    #[test]
+    fn gzip_and_zstd_round_trip() {
+        let (bytes, text) = round_trip(&temp_path("gzip", ".csv.gz"), None);
+        assert!(bytes.starts_with(GZIP_MAGIC));
+        assert_eq!(text, TEXT);
+
+        let (bytes, text) = round_trip(&temp_path("zstd", ".csv.zst"), None);
+        assert!(bytes.starts_with(ZSTD_MAGIC));
+        assert_eq!(text, TEXT);
+
+        let (bytes, text) = round_trip(&temp_path("plain", ".csv"), None);
+        assert_eq!(bytes, TEXT.as_bytes());
+        assert_eq!(text, TEXT);
+    }
+
+    #[test]
+    fn compress_overrides_the_extension() {
+        let (bytes, text) = round_trip(&temp_path("forced-zstd", ".csv"), Some(Compression::Zstd));
+        assert!(bytes.starts_with(ZSTD_MAGIC));
+        assert_eq!(text, TEXT);
+
+        let (bytes, _) = round_trip(
+            &temp_path("forced-none", ".csv.gz"),
+            Some(Compression::None),
+        );
+        assert_eq!(bytes, TEXT.as_bytes());
+    }
+
+    #[test]
+    fn open_goes_by_the_magic_bytes() {
+        // A gzip file without its extension, and a plain file with one
+        let (bytes, text) = round_trip(&temp_path("gzip-unnamed", ".csv"), Some(Compression::Gzip));
+        assert!(bytes.starts_with(GZIP_MAGIC));
+        assert_eq!(text, TEXT);
+
+        let (bytes, text) = round_trip(
+            &temp_path("plain-named", ".csv.zst"),
+            Some(Compression::None),
+        );
+        assert_eq!(bytes, TEXT.as_bytes());
+        assert_eq!(text, TEXT);
+
+        let empty = temp_path("empty", ".gz");
+        fs::write(&empty, "").unwrap();
+        let mut text = String::new();
+        open(empty.to_str().unwrap())
+            .unwrap()
+            .read_to_string(&mut text)
+            .unwrap();
+        fs::remove_file(&empty).unwrap();
+        assert_eq!(text, "");
+    }
+}
@@ -0,0 +1,75 @@
+// Module for turning binary run files into csv or json
+use crate::binary::BinaryReader;
+use crate::compress::{self, Compression};
+use crate::ids::PlayerCount;
+use crate::loot;
+use crate::observed::run_json;
+use crate::writer;
+use anyhow::{Error, Result};
+use csv::Writer;
+use std::io::Write;
+
+#[derive(clap::Args, Debug)]
+pub struct ConvertArgs {
+    /// Binary run file, written with `--format binary`
+    input: String,
+
+    /// Format to convert to, json uses the observed runs schema `compare` reads
+    #[arg(long, value_enum, default_value_t = Format::Csv)]
+    to: Format,
+
+    /// Output file, if not used, print to stdout
+    #[arg(short, long)]
+    output_file: Option<String>,
+
+    /// Compress the output, picked by the file extension (.gz, .zst) if not used
+    #[arg(long, value_enum)]
+    compress: Option<Compression>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    Csv,
+    Json,
+}
+
+/// Converts the runs one record at a time, so files of any size fit in memory
+pub fn run(args: &ConvertArgs) -> Result<(), Error> {
+    let reader = BinaryReader::open(&args.input)?;
+    let mut out = compress::create(&args.output_file, args.compress)?;
+
+    match args.to {
+        Format::Csv => {
+            let mut wtr = Writer::from_writer(out);
+            let header_player_count = reader.header().player_count.unwrap_or(PlayerCount::new(1)?);
+            writer::field_wtr_headers(&mut wtr, &false, &header_player_count, &false)?;
+            for run in reader {
+                let run = run?;
+                writer::field_wtr(
+                    &mut wtr,
+                    &run.ts,
+                    &run.flat_items(),
+                    &false,
+                    &run.player_count,
+                    None,
+                )?;
+            }
+            wtr.into_inner().map_err(|e| e.into_error())?.finish()
+        }
+        Format::Json => {
+            write!(out, "[")?;
+            for (i, run) in reader.enumerate() {
+                let run = run?;
+                let loot_counts = loot::player_loot::loot_counts(run.player_count);
+                let items = run.flat_items();
+                let json = run_json(&(run.ts, items), &run.player_count, &loot_counts);
+                if i > 0 {
+                    write!(out, ",")?;
+                }
+                write!(out, "\n{}", json)?;
+            }
+            writeln!(out, "\n]")?;
+            out.finish()
+        }
+    }
+}
@@ -0,0 +1,246 @@
+// Module for how often pairs of items are found in the same run
+use crate::compress::{self, Compression, Output};
+use crate::ids::{ItemId, PlayerCount};
+use crate::loot;
+use crate::{simulate_runs, PoolArgs, RunSettings, SimArgs};
+use anyhow::{Error, Result};
+use csv::Writer;
+use loot::IT_COUNT;
+use serde_json::{json, Value};
+use std::io::Write;
+
+#[derive(clap::Args, Debug)]
+pub struct CooccurrenceArgs {
+    #[command(flatten)]
+    sim: SimArgs,
+
+    #[command(flatten)]
+    pool: PoolArgs,
+
+    /// Output format, csv has a row per pair found, json full matrices for heatmaps
+    #[arg(long, value_enum, default_value_t = Format::Csv)]
+    format: Format,
+
+    /// Output file, if not used, print to stdout
+    #[arg(short, long)]
+    output_file: Option<String>,
+
+    /// Compress the output, picked by the file extension (.gz, .zst) if not used
+    #[arg(long, value_enum)]
+    compress: Option<Compression>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    Csv,
+    Json,
+}
+
+/// Runs each pair of items was found together in, the diagonal is each item alone
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cooccurrence {
+    pub runs: u64,
+    pub counts: Vec<u64>, // [item * IT_COUNT + item]
+}
+
+impl Default for Cooccurrence {
+    fn default() -> Self {
+        Cooccurrence {
+            runs: 0,
+            counts: vec![0; *IT_COUNT * *IT_COUNT],
+        }
+    }
+}
+
+impl Cooccurrence {
+    pub fn add(&mut self, items: &[ItemId]) {
+        self.runs += 1;
+        for a in items {
+            for b in items {
+                self.counts[a.index() * *IT_COUNT + b.index()] += 1;
+            }
+        }
+    }
+
+    pub fn merge(mut self, other: Cooccurrence) -> Cooccurrence {
+        self.runs += other.runs;
+        for (x, y) in self.counts.iter_mut().zip(&other.counts) {
+            *x += y;
+        }
+        self
+    }
+
+    pub fn count(&self, a: &ItemId, b: &ItemId) -> u64 {
+        self.counts[a.index() * *IT_COUNT + b.index()]
+    }
+
+    /// Probability of a run finding both items
+    pub fn probability(&self, a: &ItemId, b: &ItemId) -> f64 {
+        match self.runs {
+            0 => 0.0,
+            runs => self.count(a, b) as f64 / runs as f64,
+        }
+    }
+
+    /// How much more often the items are found together than if they were independent,
+    /// none if either is never found
+    pub fn lift(&self, a: &ItemId, b: &ItemId) -> Option<f64> {
+        let expected = self.probability(a, a) * self.probability(b, b);
+        (expected > 0.0).then(|| self.probability(a, b) / expected)
+    }
+}
+
+/// Simulates `game_count` runs and counts the items found together
+pub fn simulate(game_count: &usize, settings: &RunSettings) -> Result<Cooccurrence, Error> {
+    simulate_runs(
+        game_count,
+        settings,
+        Cooccurrence::default,
+        |mut acc, _, items| {
+            acc.add(items);
+            acc
+        },
+        Cooccurrence::merge,
+    )
+}
+
+/// Computes a matrix per player count and writes them as csv or json
+pub fn run(args: &CooccurrenceArgs) -> Result<(), Error> {
+    let pools = args.pool.load()?;
+    let game_count = args.sim.run_count as usize;
+    let matrices: Vec<(PlayerCount, Cooccurrence)> = args
+        .sim
+        .player_count
+        .iter()
+        .map(|player_count| {
+            let settings = RunSettings::new(args.sim.seed, *player_count, &pools);
+            let matrix = simulate(&game_count, &settings)?;
+            Ok((*player_count, matrix))
+        })
+        .collect::<Result<_, Error>>()?;
+
+    let mut out = compress::create(&args.output_file, args.compress)?;
+    match args.format {
+        Format::Csv => write_csv(out, &matrices),
+        Format::Json => {
+            serde_json::to_writer(&mut out, &to_json(&matrices))?;
+            writeln!(out)?;
+            out.finish()
+        }
+    }
+}
+
+/// One row per pair found at least once on its own, `a` before `b`
+fn write_csv(out: Output, matrices: &[(PlayerCount, Cooccurrence)]) -> Result<(), Error> {
+    let mut wtr = Writer::from_writer(out);
+    wtr.write_record([
+        "player_count",
+        "item_a",
+        "item_b",
+        "runs",
+        "probability_a",
+        "probability_b",
+        "probability_both",
+        "lift",
+    ])?;
+    for (player_count, matrix) in matrices {
+        for a in ItemId::all() {
+            for b in ItemId::all().filter(|b| *b > a) {
+                let Some(lift) = matrix.lift(&a, &b) else {
+                    continue;
+                };
+                wtr.write_record([
+                    player_count.to_string(),
+                    a.to_string(),
+                    b.to_string(),
+                    matrix.runs.to_string(),
+                    format!("{:.6}", matrix.probability(&a, &a)),
+                    format!("{:.6}", matrix.probability(&b, &b)),
+                    format!("{:.6}", matrix.probability(&a, &b)),
+                    format!("{:.4}", lift),
+                ])?;
+            }
+        }
+    }
+    wtr.into_inner().map_err(|e| e.into_error())?.finish()
+}
+
+/// Item names and, per player count, full matrices indexed [a][b] in the same order
+fn to_json(matrices: &[(PlayerCount, Cooccurrence)]) -> Value {
+    let items: Vec<&str> = ItemId::all().map(|i| i.name()).collect();
+    let per_player_count: Vec<Value> = matrices
+        .iter()
+        .map(|(player_count, matrix)| {
+            let grid = |f: &dyn Fn(&ItemId, &ItemId) -> Value| -> Vec<Vec<Value>> {
+                ItemId::all()
+                    .map(|a| ItemId::all().map(|b| f(&a, &b)).collect())
+                    .collect()
+            };
+            json!({
+                "player_count": player_count.get(),
+                "runs": matrix.runs,
+                "probability": grid(&|a, b| json!(matrix.probability(a, b))),
+                "lift": grid(&|a, b| json!(matrix.lift(a, b))),
+            })
+        })
+        .collect();
+    json!({
+        "items": items,
+        "matrices": per_player_count,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::{ItemPools, Rules};
+    use crate::stats;
+    use crate::unlocks::Unlocks;
+
+    const RUNS: usize = 2000;
+
+    fn item(index: usize) -> ItemId {
+        ItemId::new(index).unwrap()
+    }
+
+    #[test]
+    fn pairs_count_both_ways() {
+        let mut matrix = Cooccurrence::default();
+        matrix.add(&[item(0), item(5)]);
+        matrix.add(&[item(0), item(7)]);
+        assert_eq!(matrix.count(&item(0), &item(0)), 2);
+        assert_eq!(matrix.count(&item(0), &item(5)), 1);
+        assert_eq!(matrix.count(&item(5), &item(0)), 1);
+        assert_eq!(matrix.count(&item(5), &item(7)), 0);
+        assert_eq!(matrix.probability(&item(0), &item(7)), 0.5);
+        // Always found with item 0, so no more often than on its own
+        assert_eq!(matrix.lift(&item(0), &item(7)), Some(1.0));
+        assert_eq!(matrix.lift(&item(0), &item(9)), None);
+    }
+
+    #[test]
+    fn simulated_counts_are_symmetric_and_match_the_stats() {
+        let pools = ItemPools::new(&Unlocks::all(), Rules::default());
+        let player_count = PlayerCount::new(3).unwrap();
+        let settings = RunSettings::new(Some(11), player_count, &pools);
+        let matrix = simulate(&RUNS, &settings).unwrap();
+        assert_eq!(matrix, simulate(&RUNS, &settings).unwrap());
+        assert_eq!(matrix.runs, RUNS as u64);
+
+        let stats = stats::simulate(&RUNS, &settings).unwrap();
+        for a in ItemId::all() {
+            assert_eq!(matrix.count(&a, &a), stats.item_found(&a));
+            for b in ItemId::all() {
+                assert_eq!(matrix.count(&a, &b), matrix.count(&b, &a));
+                assert!(matrix.count(&a, &b) <= matrix.count(&a, &a));
+            }
+        }
+
+        // Every run adds its items squared
+        let per_run = loot::player_loot::loot_sum(player_count) as u64;
+        assert_eq!(
+            matrix.counts.iter().sum::<u64>(),
+            RUNS as u64 * per_run * per_run
+        );
+    }
+}
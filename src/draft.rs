@@ -0,0 +1,289 @@
+// Module for splitting the loot of every treasuresphere among players
+use crate::error::SimError;
+use crate::ids::{ItemId, PlayerCount};
+use crate::loot;
+use crate::unlocks::parse_item;
+use anyhow::{bail, Context, Error, Result};
+use loot::sets;
+use rand::seq::IndexedRandom;
+use rand_chacha::ChaCha8Rng;
+use std::fs;
+
+/// How a player picks an item from what is left in a treasuresphere
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Policy {
+    /// Any item left, uniformly
+    Random,
+    /// First item left in the player's priority list, random if none are left
+    Priority,
+    /// Item of the set the player holds the most pieces of, random on ties
+    SetSeeking,
+}
+
+impl std::fmt::Display for Policy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Policy::Random => "random",
+            Policy::Priority => "priority",
+            Policy::SetSeeking => "set-seeking",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Picking policy plus the priority lists used by [`Policy::Priority`]
+#[derive(Debug, Clone)]
+pub struct Drafter {
+    pub policy: Policy,
+    priorities: Vec<Vec<ItemId>>, // per player, ranked best first
+}
+
+impl Drafter {
+    /// `priority_files` are read in player order, players without a file use the last one
+    pub fn new(policy: Policy, priority_files: &[String]) -> Result<Self, Error> {
+        match (policy, priority_files.is_empty()) {
+            (Policy::Priority, true) => {
+                bail!("The priority policy needs at least one --priority file")
+            }
+            (Policy::Random | Policy::SetSeeking, false) => bail!(
+                "--priority files are only read with --draft priority, not {}",
+                policy
+            ),
+            _ => {}
+        }
+        let priorities = priority_files
+            .iter()
+            .map(|path| read_priority(path))
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(Drafter { policy, priorities })
+    }
+
+    /// Splits the items found in a run into one loadout per player
+    ///
+    /// In every treasuresphere players pick one item at a time, round robin,
+    /// until it is empty. The first pick rotates every treasuresphere so
+    /// nobody always picks first.
+    pub fn draft(
+        &self,
+        items: &[ItemId],
+        loot_counts: &[usize],
+        player_count: &PlayerCount,
+        rng: &mut ChaCha8Rng,
+    ) -> Result<Vec<Vec<ItemId>>, SimError> {
+        let player_count = player_count.get();
+        let mut loadouts: Vec<Vec<ItemId>> = vec![Vec::new(); player_count];
+
+        let mut start = 0;
+        for (t, loot_count) in loot_counts.iter().enumerate() {
+            let end = start + loot_count;
+            let mut left: Vec<ItemId> = items
+                .get(start..end)
+                .ok_or(SimError::IndexOutOfRange {
+                    what: "loot",
+                    index: end - 1,
+                    len: items.len(),
+                })?
+                .to_vec();
+            start = end;
+
+            let mut player = t % player_count;
+            while !left.is_empty() {
+                let pick = self.pick(&left, &loadouts[player], &player, rng)?;
+                loadouts[player].push(left.remove(pick));
+                player = (player + 1) % player_count;
+            }
+        }
+
+        Ok(loadouts)
+    }
+
+    /// Index in `left` of the item the player takes
+    fn pick(
+        &self,
+        left: &[ItemId],
+        held: &[ItemId],
+        player: &usize,
+        rng: &mut ChaCha8Rng,
+    ) -> Result<usize, SimError> {
+        let candidates: Vec<usize> = match self.policy {
+            Policy::Random => (0..left.len()).collect(),
+            Policy::Priority => {
+                let list = self
+                    .priorities
+                    .get(*player)
+                    .or(self.priorities.last())
+                    .ok_or(SimError::IndexOutOfRange {
+                        what: "priority list",
+                        index: *player,
+                        len: 0,
+                    })?;
+                match list.iter().find_map(|x| left.iter().position(|it| it == x)) {
+                    Some(i) => vec![i],
+                    None => (0..left.len()).collect(),
+                }
+            }
+            Policy::SetSeeking => {
+                let pieces = |item: &ItemId| {
+                    held.iter()
+                        .filter(|x| sets::set_of(x) == sets::set_of(item))
+                        .count()
+                };
+                let most = left.iter().map(pieces).max().unwrap_or(0);
+                (0..left.len())
+                    .filter(|i| pieces(&left[*i]) == most)
+                    .collect()
+            }
+        };
+
+        candidates
+            .choose(rng)
+            .copied()
+            .ok_or(SimError::IndexOutOfRange {
+                what: "item left to pick",
+                index: 0,
+                len: 0,
+            })
+    }
+}
+
+/// Reads a ranked priority list, one item per line, `#` starts a comment
+fn read_priority(path: &str) -> Result<Vec<ItemId>, Error> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Could not read priority list '{}'", path))?;
+    let mut list = Vec::new();
+    for (line_no, line) in text.lines().enumerate() {
+        let entry = line.split('#').next().unwrap_or("").trim();
+        if entry.is_empty() {
+            continue;
+        }
+        list.push(parse_item(entry).with_context(|| format!("{}:{}", path, line_no + 1))?);
+    }
+    Ok(list)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::run_rng;
+
+    fn items(indices: &[usize]) -> Vec<ItemId> {
+        indices.iter().map(|x| ItemId::new(*x).unwrap()).collect()
+    }
+
+    fn players(count: usize) -> PlayerCount {
+        PlayerCount::new(count).unwrap()
+    }
+
+    fn drafter(policy: Policy, priorities: &[&[usize]]) -> Drafter {
+        Drafter {
+            policy,
+            priorities: priorities.iter().map(|x| items(x)).collect(),
+        }
+    }
+
+    #[test]
+    fn picks_go_round_robin_and_the_first_pick_rotates() {
+        // Everyone ranks items by index, so each pick is the lowest index left
+        let drafter = drafter(Policy::Priority, &[&(0..20).collect::<Vec<_>>()]);
+        let loadouts = drafter
+            .draft(
+                &items(&[0, 1, 2, 3, 4, 10, 11, 12]),
+                &[5, 3],
+                &players(2),
+                &mut run_rng(&Some(1), &0),
+            )
+            .unwrap();
+        assert_eq!(
+            loadouts,
+            vec![items(&[0, 2, 4, 11]), items(&[1, 3, 10, 12])]
+        );
+    }
+
+    #[test]
+    fn every_item_goes_to_exactly_one_player() {
+        let found = items(&(40..66).collect::<Vec<_>>());
+        for policy in [Policy::Random, Policy::SetSeeking] {
+            for i in 0..20 {
+                let loadouts = drafter(policy, &[])
+                    .draft(
+                        &found,
+                        &[5, 5, 4, 4, 4, 4],
+                        &players(3),
+                        &mut run_rng(&Some(2), &i),
+                    )
+                    .unwrap();
+                let mut drafted = loadouts.concat();
+                drafted.sort_unstable();
+                assert_eq!(drafted, found);
+                assert!(loadouts.iter().all(|x| x.len() == 8 || x.len() == 9));
+            }
+        }
+    }
+
+    #[test]
+    fn priority_takes_each_players_best_item_left() {
+        // Player 1 has no list of their own and reuses the last one
+        let drafter = drafter(Policy::Priority, &[&[7, 3], &[5, 3, 7]]);
+        let loadouts = drafter
+            .draft(
+                &items(&[3, 5, 7, 9]),
+                &[4],
+                &players(3),
+                &mut run_rng(&Some(1), &0),
+            )
+            .unwrap();
+        assert_eq!(loadouts[0][0], items(&[7])[0]);
+        assert_eq!(loadouts[1][0], items(&[5])[0]);
+        assert_eq!(loadouts[2][0], items(&[3])[0]);
+        // Nothing of player 0's list is left for the last pick, so it's the one left
+        assert_eq!(loadouts[0][1], items(&[9])[0]);
+    }
+
+    #[test]
+    fn set_seeking_takes_the_set_with_the_most_pieces() {
+        // Sets are 8 consecutive items, player 0 holds 2 night pieces (8..16)
+        let drafter = drafter(Policy::SetSeeking, &[]);
+        for i in 0..20 {
+            let mut rng = run_rng(&Some(4), &i);
+            let pick = drafter
+                .pick(&items(&[0, 30, 12, 50]), &items(&[8, 9, 1]), &0, &mut rng)
+                .unwrap();
+            assert_eq!(pick, 2);
+
+            // Nothing held, every item ties
+            let pick = drafter
+                .pick(&items(&[0, 30, 12, 50]), &[], &0, &mut rng)
+                .unwrap();
+            assert!(pick < 4);
+        }
+    }
+
+    #[test]
+    fn draft_errors_instead_of_panicking() {
+        let mut rng = run_rng(&Some(1), &0);
+        let error = drafter(Policy::Random, &[])
+            .draft(&items(&[0, 1]), &[5], &players(2), &mut rng)
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            SimError::IndexOutOfRange { what: "loot", .. }
+        ));
+        let error = drafter(Policy::Priority, &[])
+            .draft(&items(&[0, 1]), &[2], &players(2), &mut rng)
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            SimError::IndexOutOfRange {
+                what: "priority list",
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn priority_files_only_go_with_the_priority_policy() {
+        assert!(Drafter::new(Policy::Priority, &[]).is_err());
+        assert!(Drafter::new(Policy::Random, &["list.txt".to_string()]).is_err());
+        assert!(Drafter::new(Policy::SetSeeking, &[]).is_ok());
+    }
+}
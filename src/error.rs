@@ -0,0 +1,34 @@
+// Module for the errors the generator and writer pass up instead of panicking
+use crate::ids::SpherePos;
+use crate::loot::treasuresphere::Colors as Treasuresphere;
+
+/// What can go wrong generating or writing a run
+#[derive(Debug, thiserror::Error)]
+pub enum SimError {
+    #[error("Invalid player count: {0}\nPlease enter a number from 1 to 4.")]
+    InvalidPlayerCount(usize),
+
+    #[error("{what} index {index} out of range, there are {len}")]
+    IndexOutOfRange {
+        what: &'static str,
+        index: usize,
+        len: usize,
+    },
+
+    #[error(
+        "Not enough unlocked and allowed items in the {color} treasuresphere pool (position {position})"
+    )]
+    PoolExhausted {
+        color: Treasuresphere,
+        position: SpherePos,
+    },
+
+    #[error("{0} has no binary code")]
+    NoBinaryCode(String),
+
+    #[error("Relative item columns aren't supported")]
+    RelativeColumns,
+
+    #[error("Could not write the run: {0}")]
+    Writer(#[from] csv::Error),
+}
@@ -0,0 +1,513 @@
+// Module for the --filter predicate language over a game run
+//
+// expr  := and ("or" and)*
+// and   := not ("and" not)*
+// not   := "not" not | "(" expr ")" | cmp
+// cmp   := term (("==" | "!=" | "<" | "<=" | ">" | ">=") term)?
+// term  := NUMBER | COLOR | ITEM | ts_T | it_T_I | items | sets
+//        | has(ITEM) | set(NAME) | color(COLOR) | pos(ITEM) | "(" term ")"
+//
+// "(" starts a group of conditions, unless a comparison follows its ")": then it's
+// a term in parentheses, so `(items) > 3` works like `items > 3`.
+use crate::ids::{ItemId, SpherePos};
+use crate::loot;
+use anyhow::{bail, Error, Result};
+use loot::sets;
+use loot::treasuresphere::{Colors as Treasuresphere, ITEM_NAMES};
+use loot::{IT_FOUND_MAX_PER_TS, TS_COUNT};
+
+/// A game run as the filter sees it
+pub struct RunView<'a> {
+    pub ts: &'a [Treasuresphere],
+    pub items: &'a [ItemId],
+    pub loot_counts: &'a [usize],
+}
+
+impl RunView<'_> {
+    /// Items found in the t-th treasuresphere
+    fn items_in(&self, t: SpherePos) -> &[ItemId] {
+        let start: usize = self.loot_counts[..t.index()].iter().sum();
+        &self.items[start..start + self.loot_counts[t.index()]]
+    }
+
+    /// Treasuresphere the item was found in
+    fn position(&self, item: &ItemId) -> Option<SpherePos> {
+        SpherePos::all()
+            .take(self.loot_counts.len())
+            .find(|t| self.items_in(*t).contains(item))
+    }
+}
+
+/// A parsed `--filter` expression
+#[derive(Debug, Clone)]
+pub struct Filter {
+    source: String,
+    expr: Expr,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Has(ItemId),
+    Cmp(Term, Op, Term),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone)]
+enum Term {
+    Num(i64),
+    Color(Treasuresphere),
+    Item(ItemId),
+    Ts(SpherePos),
+    It(SpherePos, usize), // position, then the item's slot in it
+    Items,
+    Sets,
+    SetCount(usize),
+    ColorCount(Treasuresphere),
+    Pos(ItemId),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Num,
+    Color,
+    Item,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Value {
+    Num(i64),
+    Color(Treasuresphere),
+    Item(Option<ItemId>), // None for empty item slots, i.e. it_5_4 in 1p
+}
+
+impl Term {
+    fn kind(&self) -> Kind {
+        match self {
+            Term::Color(_) | Term::Ts(_) => Kind::Color,
+            Term::Item(_) | Term::It(_, _) => Kind::Item,
+            _ => Kind::Num,
+        }
+    }
+
+    fn eval(&self, run: &RunView) -> Value {
+        match self {
+            Term::Num(n) => Value::Num(*n),
+            Term::Color(c) => Value::Color(*c),
+            Term::Item(item) => Value::Item(Some(*item)),
+            Term::Ts(t) => Value::Color(run.ts[t.index()]),
+            Term::It(t, i) => Value::Item(run.items_in(*t).get(*i).copied()),
+            Term::Items => Value::Num(run.items.len() as i64),
+            Term::Sets => {
+                let mut touched: Vec<usize> = run.items.iter().map(sets::set_of).collect();
+                touched.sort_unstable();
+                touched.dedup();
+                Value::Num(touched.len() as i64)
+            }
+            Term::SetCount(set) => {
+                Value::Num(run.items.iter().filter(|x| sets::set_of(x) == *set).count() as i64)
+            }
+            Term::ColorCount(color) => {
+                Value::Num(run.ts.iter().filter(|x| *x == color).count() as i64)
+            }
+            Term::Pos(item) => {
+                Value::Num(run.position(item).map_or(*TS_COUNT, SpherePos::index) as i64)
+            }
+        }
+    }
+}
+
+impl Expr {
+    fn eval(&self, run: &RunView) -> bool {
+        match self {
+            Expr::Or(a, b) => a.eval(run) || b.eval(run),
+            Expr::And(a, b) => a.eval(run) && b.eval(run),
+            Expr::Not(a) => !a.eval(run),
+            Expr::Has(item) => run.items.contains(item),
+            Expr::Cmp(a, op, b) => match (a.eval(run), b.eval(run)) {
+                (Value::Num(x), Value::Num(y)) => match op {
+                    Op::Eq => x == y,
+                    Op::Ne => x != y,
+                    Op::Lt => x < y,
+                    Op::Le => x <= y,
+                    Op::Gt => x > y,
+                    Op::Ge => x >= y,
+                },
+                (x, y) => (x == y) == (*op == Op::Eq),
+            },
+        }
+    }
+}
+
+impl Filter {
+    pub fn parse(source: &str) -> Result<Self, Error> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.expr()?;
+        if let Some(token) = parser.peek() {
+            bail!("Unexpected '{}' in filter", token);
+        }
+        Ok(Filter {
+            source: source.to_string(),
+            expr,
+        })
+    }
+
+    pub fn matches(&self, run: &RunView) -> bool {
+        self.expr.eval(run)
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<String>, Error> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' | '\n' => {}
+            '(' | ')' => tokens.push(c.to_string()),
+            '=' | '!' | '<' | '>' => {
+                let mut op = c.to_string();
+                if chars.peek() == Some(&'=') {
+                    op.push(chars.next().unwrap_or('='));
+                }
+                if op == "=" || op == "!" {
+                    bail!("Unknown operator '{}' in filter, use == or !=", op);
+                }
+                tokens.push(op);
+            }
+            c if c.is_ascii_alphanumeric() || c == '_' || c == '-' => {
+                let mut word = c.to_string();
+                while let Some(n) = chars.peek() {
+                    if n.is_ascii_alphanumeric() || *n == '_' {
+                        word.push(*n);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(word);
+            }
+            _ => bail!("Unexpected '{}' in filter", c),
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(|x| x.as_str())
+    }
+
+    fn next(&mut self) -> Result<String, Error> {
+        match self.tokens.get(self.pos) {
+            Some(token) => {
+                self.pos += 1;
+                Ok(token.clone())
+            }
+            None => bail!("Filter ended early"),
+        }
+    }
+
+    /// Source text of the tokens from `start` up to the current one
+    fn text(&self, start: usize) -> String {
+        let is_word = |x: &str| {
+            x.chars()
+                .all(|c| c.is_ascii_alphanumeric() || "_-".contains(c))
+        };
+        let mut text = String::new();
+        let mut after_word = false;
+        for token in &self.tokens[start..self.pos] {
+            let word = is_word(token);
+            if word && after_word {
+                text.push(' ');
+            }
+            text.push_str(token);
+            after_word = word;
+        }
+        text
+    }
+
+    /// The comparison operator coming next, if any
+    fn comparison(&self) -> Option<Op> {
+        match self.peek() {
+            Some("==") => Some(Op::Eq),
+            Some("!=") => Some(Op::Ne),
+            Some("<") => Some(Op::Lt),
+            Some("<=") => Some(Op::Le),
+            Some(">") => Some(Op::Gt),
+            Some(">=") => Some(Op::Ge),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), Error> {
+        let next = self.next()?;
+        if next != token {
+            bail!("Expected '{}' in filter, found '{}'", token, next);
+        }
+        Ok(())
+    }
+
+    fn expr(&mut self) -> Result<Expr, Error> {
+        let mut lhs = self.and()?;
+        while self.peek() == Some("or") {
+            self.pos += 1;
+            lhs = Expr::Or(Box::new(lhs), Box::new(self.and()?));
+        }
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> Result<Expr, Error> {
+        let mut lhs = self.not()?;
+        while self.peek() == Some("and") {
+            self.pos += 1;
+            lhs = Expr::And(Box::new(lhs), Box::new(self.not()?));
+        }
+        Ok(lhs)
+    }
+
+    fn not(&mut self) -> Result<Expr, Error> {
+        match self.peek() {
+            Some("not") => {
+                self.pos += 1;
+                Ok(Expr::Not(Box::new(self.not()?)))
+            }
+            Some("(") => {
+                let start = self.pos;
+                self.pos += 1;
+                let group = self.expr().and_then(|expr| self.expect(")").map(|_| expr));
+                match group {
+                    Ok(expr) if self.comparison().is_none() => Ok(expr),
+                    group => {
+                        // A term in parentheses, i.e. `(items) > 3`
+                        self.pos = start;
+                        self.cmp().map_err(|e| group.err().unwrap_or(e))
+                    }
+                }
+            }
+            _ => self.cmp(),
+        }
+    }
+
+    fn cmp(&mut self) -> Result<Expr, Error> {
+        let lhs_start = self.pos;
+        let lhs = self.term()?;
+        let lhs_text = self.text(lhs_start);
+        let Some(op) = self.comparison() else {
+            // A lone item means the run has it
+            return match lhs {
+                Term::Item(item) => Ok(Expr::Has(item)),
+                _ => bail!("Expected a comparison after '{}' in filter", lhs_text),
+            };
+        };
+        self.pos += 1;
+        let rhs_start = self.pos;
+        let rhs = self.term()?;
+
+        if lhs.kind() != rhs.kind() {
+            bail!(
+                "Can't compare '{}' with '{}' in filter",
+                lhs_text,
+                self.text(rhs_start)
+            );
+        }
+        if lhs.kind() != Kind::Num && !matches!(op, Op::Eq | Op::Ne) {
+            bail!("Only == and != compare treasurespheres and items in filter");
+        }
+        Ok(Expr::Cmp(lhs, op, rhs))
+    }
+
+    fn term(&mut self) -> Result<Term, Error> {
+        let word = self.next()?;
+
+        if word == "(" {
+            let term = self.term()?;
+            self.expect(")")?;
+            return Ok(term);
+        }
+        if let Ok(n) = word.parse::<i64>() {
+            return Ok(Term::Num(n));
+        }
+        if let Ok(color) = word.parse::<Treasuresphere>() {
+            return Ok(Term::Color(color));
+        }
+        if let Some(item) = ITEM_NAMES.get_index(word.as_str()) {
+            return Ok(Term::Item(ItemId::new(item)?));
+        }
+        if let Some(t) = word.strip_prefix("ts_") {
+            return Ok(Term::Ts(SpherePos::new(index(t, *TS_COUNT)?)?));
+        }
+        if let Some((t, i)) = word.strip_prefix("it_").and_then(|x| x.split_once('_')) {
+            return Ok(Term::It(
+                SpherePos::new(index(t, *TS_COUNT)?)?,
+                index(i, *IT_FOUND_MAX_PER_TS)?,
+            ));
+        }
+
+        match word.as_str() {
+            "items" => Ok(Term::Items),
+            "sets" => Ok(Term::Sets),
+            "has" | "set" | "color" | "pos" => {
+                self.expect("(")?;
+                let arg = self.next()?;
+                self.expect(")")?;
+                match word.as_str() {
+                    "set" => match sets::set_index(&arg) {
+                        Some(set) => Ok(Term::SetCount(set)),
+                        None => bail!("Unknown set in filter: {}", arg),
+                    },
+                    "color" => Ok(Term::ColorCount(arg.parse()?)),
+                    _ => {
+                        let Some(item) = ITEM_NAMES.get_index(arg.as_str()) else {
+                            bail!("Unknown item in filter: {}", arg);
+                        };
+                        let item = ItemId::new(item)?;
+                        match word.as_str() {
+                            "pos" => Ok(Term::Pos(item)),
+                            _ => Ok(Term::Item(item)), // has(ITEM) reads as a lone item
+                        }
+                    }
+                }
+            }
+            _ => bail!("Unknown word in filter: {}", word),
+        }
+    }
+}
+
+fn index(s: &str, max: usize) -> Result<usize, Error> {
+    match s.parse::<usize>() {
+        Ok(i) if i < max => Ok(i),
+        _ => bail!("Index '{}' in filter must be below {}", s, max),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ids::PlayerCount;
+    use loot::player_loot::loot_counts;
+
+    /// Checks the filter against a 1p run of normal, opal, ... finding items 0, 1, 2, ...
+    fn check(source: &str) -> bool {
+        let ts = [
+            Treasuresphere::Normal,
+            Treasuresphere::Opal,
+            Treasuresphere::Sapphire,
+            Treasuresphere::Ruby,
+            Treasuresphere::Garnet,
+            Treasuresphere::Emerald,
+        ];
+        let loot_counts = loot_counts(PlayerCount::new(1).unwrap());
+        let items: Vec<ItemId> = ItemId::all().take(loot_counts.iter().sum()).collect();
+        let run = RunView {
+            ts: &ts,
+            items: &items,
+            loot_counts: &loot_counts,
+        };
+        Filter::parse(source).unwrap().matches(&run)
+    }
+
+    fn error(source: &str) -> String {
+        Filter::parse(source).unwrap_err().to_string()
+    }
+
+    fn name(index: usize) -> &'static str {
+        ItemId::new(index).unwrap().name()
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert!(check("ts_0 == normal or ts_0 == opal and ts_0 == opal"));
+        assert!(!check("(ts_0 == normal or ts_0 == opal) and ts_0 == opal"));
+        assert!(!check("ts_0 == opal and ts_0 == opal or ts_0 == opal"));
+    }
+
+    #[test]
+    fn not_applies_to_the_next_condition() {
+        assert!(check("not ts_0 == opal"));
+        assert!(!check("not ts_0 == normal and ts_1 == opal"));
+        assert!(check("not (ts_0 == normal and ts_1 == normal)"));
+        assert!(check("not not ts_1 == opal"));
+    }
+
+    #[test]
+    fn lone_items_mean_the_run_has_them() {
+        let found = name(0);
+        let missing = name(199);
+        assert!(check(found));
+        assert!(check(&format!("has({})", found)));
+        assert!(!check(missing));
+        assert!(check(&format!("not {} and {}", missing, found)));
+    }
+
+    #[test]
+    fn pos_of_a_missing_item_is_past_the_last_treasuresphere() {
+        let first_of_ts_1 = name(loot_counts(PlayerCount::new(1).unwrap())[0]);
+        assert!(check(&format!("pos({}) == 1", first_of_ts_1)));
+        assert!(check(&format!("pos({}) == 6", name(199))));
+        assert!(!check(&format!("pos({}) < 6", name(199))));
+    }
+
+    #[test]
+    fn a_term_in_parentheses_can_be_compared() {
+        assert!(check("(items) > 3"));
+        assert!(check("((items)) > 3 and (ts_0 == normal)"));
+        assert!(!check("(sets) < 0"));
+    }
+
+    #[test]
+    fn errors_quote_the_source() {
+        let item = name(7);
+        assert_eq!(
+            error(&format!("ts_0 == {}", item)),
+            format!("Can't compare 'ts_0' with '{}' in filter", item)
+        );
+        assert_eq!(
+            error("set(night) > opal"),
+            "Can't compare 'set(night)' with 'opal' in filter"
+        );
+        assert_eq!(
+            error("items"),
+            "Expected a comparison after 'items' in filter"
+        );
+        assert_eq!(
+            error("ts_0 < opal"),
+            "Only == and != compare treasurespheres and items in filter"
+        );
+    }
+
+    #[test]
+    fn malformed_filters_are_rejected() {
+        assert!(error("ts_6 == opal").contains("must be below 6"));
+        assert!(error("it_0_9 == it_0_0").contains("must be below"));
+        assert!(error("items = 3").contains("use == or !="));
+        assert!(error("has(it_no_such_item)").contains("Unknown item"));
+        assert!(error("set(nope) > 1").contains("Unknown set"));
+        assert!(error("sparkles > 1").contains("Unknown word"));
+        assert_eq!(error("items > 3 )"), "Unexpected ')' in filter");
+        assert_eq!(error("(ts_0 == opal"), "Filter ended early");
+        assert_eq!(error(""), "Filter ended early");
+    }
+}
@@ -0,0 +1,250 @@
+// Module for distributions of per-run quantities, i.e. how many sets a run touches
+use crate::compress::{self, Compression};
+use crate::ids::ItemId;
+use crate::loot;
+use crate::{simulate_runs, PoolArgs, RunSettings, SimArgs};
+use anyhow::{Error, Result};
+use csv::Writer;
+use loot::sets::{self, SET_NAMES};
+use loot::treasuresphere::{
+    Colors as Treasuresphere, IS_EMERALD, IS_GARNET, IS_OPAL, IS_RUBY, IS_SAPPHIRE,
+};
+use loot::{IT_FOUND_MAX_PER_TS, TS_COUNT};
+
+#[derive(clap::Args, Debug)]
+pub struct HistogramArgs {
+    /// Quantities to count per run, joined by ','
+    #[arg(short, long, value_enum, value_delimiter = ',', default_values_t = [Metric::SetsTouched, Metric::MaxSetPieces, Metric::NormalSpheres, Metric::HybridItems])]
+    metric: Vec<Metric>,
+
+    #[command(flatten)]
+    sim: SimArgs,
+
+    #[command(flatten)]
+    pool: PoolArgs,
+
+    /// Output file (csv), if not used, print to stdout
+    #[arg(short, long)]
+    output_file: Option<String>,
+
+    /// Compress the output, picked by the file extension (.gz, .zst) if not used
+    #[arg(long, value_enum)]
+    compress: Option<Compression>,
+}
+
+/// A quantity counted once per run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Metric {
+    /// Sets with at least one item found
+    SetsTouched,
+    /// Most items found of any one set
+    MaxSetPieces,
+    /// Normal treasurespheres
+    NormalSpheres,
+    /// Items in the pools of two treasuresphere colors
+    HybridItems,
+    /// Sets with every item found
+    CompleteSets,
+}
+
+impl std::fmt::Display for Metric {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Metric::SetsTouched => "sets-touched",
+            Metric::MaxSetPieces => "max-set-pieces",
+            Metric::NormalSpheres => "normal-spheres",
+            Metric::HybridItems => "hybrid-items",
+            Metric::CompleteSets => "complete-sets",
+        };
+        f.write_str(name)
+    }
+}
+
+impl Metric {
+    /// Value of the metric for a run
+    pub fn measure(&self, ts: &[Treasuresphere], items: &[ItemId]) -> usize {
+        let mut pieces = vec![0; SET_NAMES.len()];
+        for item in items {
+            pieces[sets::set_of(item)] += 1;
+        }
+        match self {
+            Metric::SetsTouched => pieces.iter().filter(|x| **x > 0).count(),
+            Metric::MaxSetPieces => pieces.into_iter().max().unwrap_or(0),
+            Metric::NormalSpheres => ts.iter().filter(|x| **x == Treasuresphere::Normal).count(),
+            Metric::HybridItems => items.iter().filter(|x| is_hybrid(x)).count(),
+            Metric::CompleteSets => pieces.iter().filter(|x| **x == *sets::SET_SIZE).count(),
+        }
+    }
+}
+
+/// Checks if the item is in the pools of two treasuresphere colors
+fn is_hybrid(item: &ItemId) -> bool {
+    [&IS_OPAL, &IS_SAPPHIRE, &IS_RUBY, &IS_GARNET, &IS_EMERALD]
+        .iter()
+        .filter(|x| x.contains(&item.as_u8()))
+        .count()
+        > 1
+}
+
+/// Runs per value of every metric, values can't go past the items in a run
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histograms {
+    pub runs: u64,
+    pub counts: Vec<Vec<u64>>, // [metric][value]
+}
+
+impl Histograms {
+    pub fn new(metric_count: usize) -> Self {
+        Histograms {
+            runs: 0,
+            counts: vec![vec![0; TS_COUNT * IT_FOUND_MAX_PER_TS + 1]; metric_count],
+        }
+    }
+
+    pub fn add(&mut self, metrics: &[Metric], ts: &[Treasuresphere], items: &[ItemId]) {
+        self.runs += 1;
+        for (m, metric) in metrics.iter().enumerate() {
+            let value = metric.measure(ts, items).min(self.counts[m].len() - 1);
+            self.counts[m][value] += 1;
+        }
+    }
+
+    pub fn merge(mut self, other: Histograms) -> Histograms {
+        self.runs += other.runs;
+        for (x, y) in self
+            .counts
+            .iter_mut()
+            .flatten()
+            .zip(other.counts.iter().flatten())
+        {
+            *x += y;
+        }
+        self
+    }
+}
+
+/// Simulates `game_count` runs, counting each into the histograms as it's generated
+pub fn simulate(
+    metrics: &[Metric],
+    game_count: &usize,
+    settings: &RunSettings,
+) -> Result<Histograms, Error> {
+    simulate_runs(
+        game_count,
+        settings,
+        || Histograms::new(metrics.len()),
+        |mut acc, ts, it| {
+            acc.add(metrics, ts, it);
+            acc
+        },
+        Histograms::merge,
+    )
+}
+
+/// Writes a row per player count, metric and value, up to the largest value seen
+pub fn run(args: &HistogramArgs) -> Result<(), Error> {
+    let pools = args.pool.load()?;
+    let out = compress::create(&args.output_file, args.compress)?;
+    let mut wtr = Writer::from_writer(out);
+    wtr.write_record([
+        "player_count",
+        "metric",
+        "value",
+        "runs",
+        "probability",
+        "cumulative",
+    ])?;
+
+    let game_count = args.sim.run_count as usize;
+    for player_count in &args.sim.player_count {
+        let settings = RunSettings::new(args.sim.seed, *player_count, &pools);
+        let histograms = simulate(&args.metric, &game_count, &settings)?;
+
+        for (metric, counts) in args.metric.iter().zip(&histograms.counts) {
+            let last = counts.iter().rposition(|x| *x > 0).unwrap_or(0);
+            let mut cumulative = 0;
+            for (value, count) in counts.iter().enumerate().take(last + 1) {
+                cumulative += count;
+                wtr.write_record([
+                    player_count.to_string(),
+                    metric.to_string(),
+                    value.to_string(),
+                    count.to_string(),
+                    format!("{:.6}", *count as f64 / histograms.runs as f64),
+                    format!("{:.6}", cumulative as f64 / histograms.runs as f64),
+                ])?;
+            }
+        }
+    }
+
+    wtr.into_inner().map_err(|e| e.into_error())?.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ids::PlayerCount;
+    use crate::rules::{ItemPools, Rules};
+    use crate::stats;
+    use crate::unlocks::Unlocks;
+    use loot::treasuresphere::COLORS;
+
+    const RUNS: usize = 2000;
+    const METRICS: [Metric; 5] = [
+        Metric::SetsTouched,
+        Metric::MaxSetPieces,
+        Metric::NormalSpheres,
+        Metric::HybridItems,
+        Metric::CompleteSets,
+    ];
+
+    #[test]
+    fn metrics_measure_a_run() {
+        // The first set whole, and one item of the second
+        let items: Vec<ItemId> = sets::items_in_set(&0)
+            .chain(sets::items_in_set(&1).take(1))
+            .collect();
+        let ts = [
+            Treasuresphere::Normal,
+            Treasuresphere::Ruby,
+            Treasuresphere::Normal,
+        ];
+        assert_eq!(Metric::SetsTouched.measure(&ts, &items), 2);
+        assert_eq!(Metric::MaxSetPieces.measure(&ts, &items), *sets::SET_SIZE);
+        assert_eq!(Metric::CompleteSets.measure(&ts, &items), 1);
+        assert_eq!(Metric::NormalSpheres.measure(&ts, &items), 2);
+        assert_eq!(
+            Metric::HybridItems.measure(&ts, &items),
+            items.iter().filter(|x| is_hybrid(x)).count()
+        );
+    }
+
+    #[test]
+    fn bins_hold_every_run() {
+        let pools = ItemPools::new(&Unlocks::all(), Rules::default());
+        let settings = RunSettings::new(Some(13), PlayerCount::new(2).unwrap(), &pools);
+        let histograms = simulate(&METRICS, &RUNS, &settings).unwrap();
+        assert_eq!(histograms, simulate(&METRICS, &RUNS, &settings).unwrap());
+        assert_eq!(histograms.runs, RUNS as u64);
+        for counts in &histograms.counts {
+            assert_eq!(counts.iter().sum::<u64>(), RUNS as u64);
+        }
+
+        // The Normal treasurespheres add up to the ones the stats count
+        let stats = stats::simulate(&RUNS, &settings).unwrap();
+        let normal = COLORS
+            .iter()
+            .position(|x| *x == Treasuresphere::Normal)
+            .unwrap();
+        let normal_spheres: u64 = histograms.counts[2]
+            .iter()
+            .enumerate()
+            .map(|(value, count)| value as u64 * count)
+            .sum();
+        assert_eq!(
+            normal_spheres,
+            stats.ts_counts.iter().map(|x| x[normal]).sum::<u64>()
+        );
+    }
+}
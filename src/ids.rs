@@ -0,0 +1,176 @@
+// Module for validated identifiers, so items, positions and player counts can't be mixed up
+use crate::error::SimError;
+use crate::loot::treasuresphere::ITEM_NAMES;
+use crate::loot::{IT_N, TS_N};
+use anyhow::{Error, Result};
+
+/// Index of an item in `ITEM_NAMES`, always below `IT_COUNT`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct ItemId(u8);
+
+impl ItemId {
+    pub fn new(index: usize) -> Result<Self, SimError> {
+        match index {
+            i if i < IT_N => Ok(ItemId(i as u8)),
+            _ => Err(SimError::IndexOutOfRange {
+                what: "item",
+                index,
+                len: IT_N,
+            }),
+        }
+    }
+
+    /// Every item, in index order
+    pub fn all() -> impl Iterator<Item = ItemId> {
+        (0..IT_N as u8).map(ItemId)
+    }
+
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+
+    /// The index as stored in the item tables and binary files
+    pub fn as_u8(self) -> u8 {
+        self.0
+    }
+
+    /// Name as written in files, i.e. it_topaz_charm
+    pub fn name(self) -> &'static str {
+        ITEM_NAMES.index(self.index()).copied().unwrap_or("?")
+    }
+}
+
+impl std::fmt::Display for ItemId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Position of a treasuresphere in a run, always below `TS_COUNT`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SpherePos(u8);
+
+impl SpherePos {
+    pub fn new(index: usize) -> Result<Self, SimError> {
+        match index {
+            t if t < TS_N => Ok(SpherePos(t as u8)),
+            _ => Err(SimError::IndexOutOfRange {
+                what: "treasuresphere",
+                index,
+                len: TS_N,
+            }),
+        }
+    }
+
+    /// Every position, first to last
+    pub fn all() -> impl Iterator<Item = SpherePos> {
+        (0..TS_N as u8).map(SpherePos)
+    }
+
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+
+    /// Treasurespheres from this one to the end of the run, 1 for the last one
+    pub fn spheres_left(self) -> usize {
+        TS_N - self.index()
+    }
+}
+
+impl std::fmt::Display for SpherePos {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Players in a game, always 1 to 4
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PlayerCount(u8);
+
+impl PlayerCount {
+    pub const MAX: usize = 4;
+
+    pub fn new(count: usize) -> Result<Self, SimError> {
+        match count {
+            1..=Self::MAX => Ok(PlayerCount(count as u8)),
+            _ => Err(SimError::InvalidPlayerCount(count)),
+        }
+    }
+
+    /// Every player count, 1 to 4
+    pub fn all() -> impl Iterator<Item = PlayerCount> {
+        (1..=Self::MAX as u8).map(PlayerCount)
+    }
+
+    pub fn get(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl std::fmt::Display for PlayerCount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::str::FromStr for PlayerCount {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        Ok(PlayerCount::new(s.trim().parse()?)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn items_stop_at_the_table_size() {
+        assert_eq!(ItemId::new(199).unwrap().index(), 199);
+        assert_eq!(
+            ItemId::new(200).unwrap_err().to_string(),
+            "item index 200 out of range, there are 200"
+        );
+        assert_eq!(ItemId::all().count(), IT_N);
+    }
+
+    #[test]
+    fn positions_stop_at_the_last_treasuresphere() {
+        let last = SpherePos::new(5).unwrap();
+        assert_eq!(last.spheres_left(), 1);
+        assert_eq!(SpherePos::new(0).unwrap().spheres_left(), TS_N);
+        assert_eq!(
+            SpherePos::new(6).unwrap_err().to_string(),
+            "treasuresphere index 6 out of range, there are 6"
+        );
+    }
+
+    #[test]
+    fn player_counts_are_1_to_4() {
+        assert!(matches!(
+            PlayerCount::new(0),
+            Err(SimError::InvalidPlayerCount(0))
+        ));
+        assert!(matches!(
+            PlayerCount::new(5),
+            Err(SimError::InvalidPlayerCount(5))
+        ));
+        assert_eq!(PlayerCount::new(4).unwrap().get(), PlayerCount::MAX);
+        assert_eq!(PlayerCount::all().count(), PlayerCount::MAX);
+    }
+
+    #[test]
+    fn player_counts_parse_trimmed_numbers() {
+        assert_eq!(" 3 ".parse::<PlayerCount>().unwrap().get(), 3);
+        assert!("0"
+            .parse::<PlayerCount>()
+            .unwrap_err()
+            .to_string()
+            .starts_with("Invalid player count: 0"));
+        assert!("5".parse::<PlayerCount>().is_err());
+        assert!("-1".parse::<PlayerCount>().is_err());
+        assert!("two".parse::<PlayerCount>().is_err());
+        assert!("".parse::<PlayerCount>().is_err());
+    }
+}
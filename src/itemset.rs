@@ -0,0 +1,64 @@
+// Module for a fixed-size set of items
+use crate::ids::ItemId;
+use crate::loot::IT_N;
+
+const WORDS: usize = IT_N.div_ceil(64);
+
+/// Items as a 200-bit bitset, checking and adding an item never allocates
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ItemSet([u64; WORDS]);
+
+impl ItemSet {
+    pub fn insert(&mut self, item: &ItemId) {
+        let i = item.index();
+        self.0[i / 64] |= 1 << (i % 64);
+    }
+
+    pub fn contains(&self, item: &ItemId) -> bool {
+        let i = item.index();
+        self.0[i / 64] & (1 << (i % 64)) != 0
+    }
+}
+
+impl<'a> Extend<&'a ItemId> for ItemSet {
+    fn extend<I: IntoIterator<Item = &'a ItemId>>(&mut self, items: I) {
+        for item in items {
+            self.insert(item);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(index: usize) -> ItemId {
+        ItemId::new(index).unwrap()
+    }
+
+    #[test]
+    fn word_edges_are_kept_apart() {
+        let edges = [0, 63, 64, 199];
+        let mut set = ItemSet::default();
+        set.extend(&edges.map(item));
+        for i in ItemId::all() {
+            assert_eq!(
+                set.contains(&i),
+                edges.contains(&i.index()),
+                "{}",
+                i.index()
+            );
+        }
+    }
+
+    #[test]
+    fn each_edge_sets_one_bit() {
+        for index in [0, 63, 64, 199] {
+            let mut set = ItemSet::default();
+            set.insert(&item(index));
+            set.insert(&item(index));
+            assert_eq!(set.0.iter().map(|x| x.count_ones()).sum::<u32>(), 1);
+            assert!(set.contains(&item(index)));
+        }
+    }
+}
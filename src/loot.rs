@@ -3,47 +3,260 @@
 /// Treasuresphere Count
 ///
 /// May break if changed from 6 as of right now.
-pub static TS_COUNT: &'static usize = &6usize;
+pub static TS_COUNT: &usize = &TS_N;
 
 /// Item Count in game
-pub static IT_COUNT: &'static usize = &200usize;
+pub static IT_COUNT: &usize = &IT_N;
 
 /// Max items found per Treasuresphere
-pub static IT_FOUND_MAX_PER_TS: &'static usize = &5usize;
+pub static IT_FOUND_MAX_PER_TS: &usize = &IT_FOUND_MAX_N;
+
+// The counts above as constants, for sizing arrays
+pub const TS_N: usize = 6;
+pub const IT_N: usize = 200;
+pub const IT_FOUND_MAX_N: usize = 5;
 
 /// Module to call constants based on player count
 pub mod player_loot {
+    use crate::ids::PlayerCount;
     use anyhow::{bail, Error};
 
-    pub fn loot_counts(player_count: usize) -> Result<Vec<usize>, Error> {
-        let loot_counts = match player_count {
-            1 => *ONE,
-            2 => *TWO,
-            3 => *THREE,
-            4 => *FOUR,
-            _ => bail!(
-                "Invalid player count: {}\nPlease enter a number from 1 to 4.",
-                player_count
-            ),
-        };
-        Ok(loot_counts.to_vec())
+    pub fn loot_counts(player_count: PlayerCount) -> Vec<usize> {
+        loot_table(player_count).to_vec()
+    }
+
+    /// Loot per treasuresphere without allocating, for the generator
+    pub fn loot_table(player_count: PlayerCount) -> &'static [usize; 6] {
+        &LOOT[player_count.get() - 1]
+    }
+
+    pub fn loot_sum(player_count: PlayerCount) -> usize {
+        loot_table(player_count).iter().sum()
+    }
+
+    /// Player counts to mix in one dataset, each with a weight
+    ///
+    /// Parsed from `2` (one count), `1,2,4` (even weights)
+    /// or `1:0.4,2:0.3,4:0.3` (weighted, normalized to sum to 1).
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct PlayerMix {
+        weights: Vec<(PlayerCount, f64)>,
+    }
+
+    impl PlayerMix {
+        /// Player count of a run, only rolls when there's more than one to pick from
+        pub fn pick(&self, rng: &mut impl rand::Rng) -> PlayerCount {
+            if let [(player_count, _)] = self.weights[..] {
+                return player_count;
+            }
+            let mut roll: f64 = rng.random();
+            for (player_count, weight) in &self.weights {
+                if roll < *weight {
+                    return *player_count;
+                }
+                roll -= weight;
+            }
+            self.weights.last().map_or(self.weights[0].0, |x| x.0) // float rounding
+        }
+
+        pub fn player_counts(&self) -> Vec<PlayerCount> {
+            self.weights.iter().map(|x| x.0).collect()
+        }
+    }
+
+    impl std::str::FromStr for PlayerMix {
+        type Err = Error;
+
+        fn from_str(s: &str) -> Result<Self, Error> {
+            let mut weights: Vec<(PlayerCount, f64)> = Vec::new();
+            for entry in s.split(',').map(str::trim) {
+                let (count, weight) = match entry.split_once(':') {
+                    Some((c, w)) => (c, w.trim().parse::<f64>()?),
+                    None => (entry, 1.0),
+                };
+                let count: PlayerCount = count.parse()?;
+                if !(weight > 0.0 && weight.is_finite()) {
+                    bail!("Invalid weight for {} players: {}", count, weight);
+                }
+                if weights.iter().any(|x| x.0 == count) {
+                    bail!("Player count {} given twice", count);
+                }
+                weights.push((count, weight));
+            }
+
+            let total: f64 = weights.iter().map(|x| x.1).sum();
+            weights.iter_mut().for_each(|x| x.1 /= total);
+            Ok(PlayerMix { weights })
+        }
+    }
+
+    impl std::fmt::Display for PlayerMix {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            let entries: Vec<String> = match self.weights[..] {
+                [(player_count, _)] => vec![player_count.to_string()],
+                _ => self
+                    .weights
+                    .iter()
+                    .map(|(c, w)| format!("{}:{}", c, w))
+                    .collect(),
+            };
+            f.write_str(&entries.join(","))
+        }
+    }
+
+    // Rows are player counts 1..=4, columns are treasurespheres 0..6
+    static LOOT: &[[usize; 6]; 4] = &[
+        [5, 5, 3, 3, 3, 3],
+        [5, 5, 4, 4, 4, 4],
+        [5, 5, 4, 4, 4, 4],
+        [5, 5, 5, 5, 5, 5],
+    ];
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use rand::{Rng, SeedableRng};
+        use rand_chacha::ChaCha8Rng;
+
+        fn mix(s: &str) -> PlayerMix {
+            s.parse().unwrap()
+        }
+
+        fn error(s: &str) -> String {
+            s.parse::<PlayerMix>().unwrap_err().to_string()
+        }
+
+        fn players(count: usize) -> PlayerCount {
+            PlayerCount::new(count).unwrap()
+        }
+
+        #[test]
+        fn loot_follows_the_player_count() {
+            assert_eq!(loot_counts(players(1)), vec![5, 5, 3, 3, 3, 3]);
+            assert_eq!(loot_sum(players(1)), 22);
+            assert_eq!(loot_sum(players(4)), 30);
+        }
+
+        #[test]
+        fn mixes_parse_one_even_or_weighted_counts() {
+            assert_eq!(mix("2").weights, vec![(players(2), 1.0)]);
+            assert_eq!(mix("2").to_string(), "2");
+
+            let even = mix("1, 2,4");
+            assert_eq!(
+                even.player_counts(),
+                vec![players(1), players(2), players(4)]
+            );
+            assert!(even.weights.iter().all(|x| (x.1 - 1.0 / 3.0).abs() < 1e-12));
+
+            // Weights are normalized, a count without one weighs 1
+            assert_eq!(
+                mix("1:3,4").weights,
+                vec![(players(1), 0.75), (players(4), 0.25)]
+            );
+            assert_eq!(mix("1:0.4,2:0.6").to_string(), "1:0.4,2:0.6");
+        }
+
+        #[test]
+        fn mixes_reject_bad_counts_and_weights() {
+            assert!(error("0").starts_with("Invalid player count: 0"));
+            assert!(error("5").starts_with("Invalid player count: 5"));
+            assert!("1,".parse::<PlayerMix>().is_err());
+            assert!("".parse::<PlayerMix>().is_err());
+            assert!("two".parse::<PlayerMix>().is_err());
+            assert!("1:x".parse::<PlayerMix>().is_err());
+            assert_eq!(error("1:0"), "Invalid weight for 1 players: 0");
+            assert_eq!(error("1:-0.5,2"), "Invalid weight for 1 players: -0.5");
+            assert_eq!(error("1:inf"), "Invalid weight for 1 players: inf");
+            assert_eq!(error("1:NaN"), "Invalid weight for 1 players: NaN");
+            assert_eq!(error("2,3,2:4"), "Player count 2 given twice");
+        }
+
+        #[test]
+        fn one_count_is_picked_without_rolling() {
+            let mut rng = ChaCha8Rng::seed_from_u64(1);
+            assert_eq!(mix("3").pick(&mut rng), players(3));
+            let next: u64 = rng.random();
+            assert_eq!(next, ChaCha8Rng::seed_from_u64(1).random::<u64>());
+        }
+
+        #[test]
+        fn picks_follow_the_weights() {
+            let mix = mix("1:1,4:3");
+            let mut rng = ChaCha8Rng::seed_from_u64(2);
+            let mut fours = 0;
+            for _ in 0..4000 {
+                match mix.pick(&mut rng).get() {
+                    4 => fours += 1,
+                    count => assert_eq!(count, 1),
+                }
+            }
+            // 3000 expected, the standard deviation is about 27
+            assert!((2850..=3150).contains(&fours), "{}", fours);
+        }
     }
+}
+
+/// Module for item sets, every set is 8 consecutive item indices
+pub mod sets {
+    use crate::ids::ItemId;
+
+    /// Items per set
+    pub static SET_SIZE: &usize = &8usize;
 
-    pub fn loot_sum(player_count: usize) -> Result<usize, Error> {
-        Ok(loot_counts(player_count)?.into_iter().sum())
+    /// Set names by set index, i.e. items 0..=7 are "arcane"
+    pub static SET_NAMES: &[&str; 25] = &[
+        "arcane",
+        "night",
+        "timespace",
+        "wind",
+        "bloodwolf",
+        "assassin",
+        "rockdragon",
+        "flame",
+        "gem",
+        "lightning",
+        "shrine",
+        "lucky",
+        "life",
+        "poison",
+        "depth",
+        "darkbite",
+        "timegem",
+        "youkai",
+        "haunted",
+        "gladiator",
+        "sparkblade",
+        "swiftflight",
+        "sacredflame",
+        "ruins",
+        "lakeshrine",
+    ];
+
+    /// Set index of an item
+    pub fn set_of(item: &ItemId) -> usize {
+        item.index() / SET_SIZE
+    }
+
+    /// Items belonging to a set index
+    pub fn items_in_set(set: &usize) -> impl Iterator<Item = ItemId> + use<> {
+        (set * SET_SIZE..(set + 1) * SET_SIZE).filter_map(|x| ItemId::new(x).ok())
     }
 
-    static ONE: &'static [usize; 6] = &[5, 5, 3, 3, 3, 3];
-    static TWO: &'static [usize; 6] = &[5, 5, 4, 4, 4, 4];
-    static THREE: &'static [usize; 6] = &[5, 5, 4, 4, 4, 4];
-    static FOUR: &'static [usize; 6] = &[5, 5, 5, 5, 5, 5];
+    /// Set index of a set name
+    pub fn set_index(name: &str) -> Option<usize> {
+        SET_NAMES.iter().position(|x| *x == name)
+    }
 }
 
 pub mod treasuresphere {
+    use crate::error::SimError;
+    use crate::ids::{ItemId, SpherePos};
     use phf::{OrderedMap, OrderedSet};
     use phf_macros::{phf_ordered_map, phf_ordered_set};
+    use std::sync::LazyLock;
 
-    #[derive(Debug, PartialEq, Eq)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub enum Colors {
         Normal, // Reminder that you can find Normal 3 times
         Opal,
@@ -53,47 +266,78 @@ pub mod treasuresphere {
         Emerald,
     }
 
+    /// Every treasuresphere color, in `from_index` order
+    pub static COLORS: &[Colors; 6] = &[
+        Colors::Normal,
+        Colors::Opal,
+        Colors::Sapphire,
+        Colors::Ruby,
+        Colors::Garnet,
+        Colors::Emerald,
+    ];
+
+    /// The bag of 8 treasurespheres a game draws from, Normal 3 times
+    pub static BAG: &[Colors; 8] = &[
+        Colors::Normal,
+        Colors::Normal,
+        Colors::Normal,
+        Colors::Opal,
+        Colors::Sapphire,
+        Colors::Ruby,
+        Colors::Garnet,
+        Colors::Emerald,
+    ];
+
     /// Checks if the item is valid in the current Treasuresphere position
-    pub fn is_item_in_ts_pos(item: &usize, ts_i: &usize, ts_count: &usize) -> bool {
-        let delta = ts_count - ts_i; // 1..=6
-        match NOT_IN_LAST_SPHERES.get(&(*item as u32)) {
+    pub fn is_item_in_ts_pos(item: &ItemId, pos: &SpherePos) -> bool {
+        let delta = pos.spheres_left(); // 1..=6
+        match NOT_IN_LAST_SPHERES.get(&item.as_u8()) {
             //if 2 (topaz charm), then as long as delta is 1 or 2, it returns false
-            Some(val) if val >= &delta => return false,
-            Some(_) => return true,
-            None => return true,
-        };
+            Some(val) if val >= &delta => false,
+            Some(_) => true,
+            None => true,
+        }
     }
 
+    /// Item pools in COLORS order, read once from the tables below
+    static POOLS: LazyLock<[Vec<ItemId>; 6]> = LazyLock::new(|| {
+        let pool = |table: &OrderedSet<u8>| -> Vec<ItemId> {
+            table
+                .iter()
+                .filter_map(|x| ItemId::new(*x as usize).ok())
+                .collect()
+        };
+        [
+            ItemId::all().collect(),
+            pool(&IS_OPAL),
+            pool(&IS_SAPPHIRE),
+            pool(&IS_RUBY),
+            pool(&IS_GARNET),
+            pool(&IS_EMERALD),
+        ]
+    });
+
     impl Colors {
-        pub fn items_in_ts(&self) -> Vec<usize> {
-            //This needs to be modifiable
-            match &self {
-                Colors::Normal => (0..*super::IT_COUNT).collect(),
-                Colors::Opal => IS_OPAL.iter().map(|x| *x).collect(),
-                Colors::Sapphire => IS_SAPPHIRE.iter().map(|x| *x).collect(),
-                Colors::Ruby => IS_RUBY.iter().map(|x| *x).collect(),
-                Colors::Garnet => IS_GARNET.iter().map(|x| *x).collect(),
-                Colors::Emerald => IS_EMERALD.iter().map(|x| *x).collect(),
-            }
+        /// Items of the treasuresphere's pool, in table order
+        pub fn pool(&self) -> &'static [ItemId] {
+            &POOLS[*self as usize]
         }
 
-        /// Involves weighted indices, for use when generating treasurespheres
-        pub fn from_index(index: u8) -> Self {
-            match index {
-                0 | 1 | 2 => Colors::Normal,
-                3 => Colors::Opal,
-                4 => Colors::Sapphire,
-                5 => Colors::Ruby,
-                6 => Colors::Garnet,
-                7 => Colors::Emerald,
-                _ => panic!("Unexpected treasuresphere index: {}", index),
-            }
+        /// Involves weighted indices, the treasuresphere at `index` in `BAG`
+        pub fn from_index(index: u8) -> Result<Self, SimError> {
+            BAG.get(index as usize)
+                .copied()
+                .ok_or(SimError::IndexOutOfRange {
+                    what: "treasuresphere",
+                    index: index as usize,
+                    len: BAG.len(),
+                })
         }
     }
 
-    // ToString feels so Rusty :D
-    impl ToString for Colors {
-        fn to_string(&self) -> String {
+    impl Colors {
+        /// Name as written in files and on the command line
+        pub fn name(&self) -> &'static str {
             match self {
                 Colors::Normal => "normal",
                 Colors::Opal => "opal",
@@ -102,51 +346,74 @@ pub mod treasuresphere {
                 Colors::Garnet => "garnet",
                 Colors::Emerald => "emerald",
             }
-            .to_string()
         }
     }
 
+    // Display (and with it ToString) feels so Rusty :D
+    impl std::fmt::Display for Colors {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str(self.name())
+        }
+    }
+
+    impl std::str::FromStr for Colors {
+        type Err = anyhow::Error;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "normal" => Ok(Colors::Normal),
+                "opal" => Ok(Colors::Opal),
+                "sapphire" => Ok(Colors::Sapphire),
+                "ruby" => Ok(Colors::Ruby),
+                "garnet" => Ok(Colors::Garnet),
+                "emerald" => Ok(Colors::Emerald),
+                _ => anyhow::bail!("Unknown treasuresphere: {}", s),
+            }
+        }
+    }
+
+    // Item tables, the literals are typed so the keys hash the same as the lookups
     // 0..=23 | 120..=151 => true,
-    pub static IS_OPAL: OrderedSet<usize> = phf_ordered_set! {
-        0 | 1 | 2 | 3 | 4 | 5 | 6 | 7 | 8 | 9 | 10 | 11 | 12 | 13 | 14 | 15 | 16 | 17 | 18 | 19 | 20 | 21 | 22 | 23,
-        120 | 121 | 122 | 123 | 124 | 125 | 126 | 127 | 128 | 129 | 130 | 131 | 132 | 133 | 134 | 135 | 136 | 137 | 138 | 139 | 140 | 141 | 142 | 143 | 144 | 145 | 146 | 147 | 148 | 149 | 150 | 151,
+    pub static IS_OPAL: OrderedSet<u8> = phf_ordered_set! {
+        0u8 | 1u8 | 2u8 | 3u8 | 4u8 | 5u8 | 6u8 | 7u8 | 8u8 | 9u8 | 10u8 | 11u8 | 12u8 | 13u8 | 14u8 | 15u8 | 16u8 | 17u8 | 18u8 | 19u8 | 20u8 | 21u8 | 22u8 | 23u8,
+        120u8 | 121u8 | 122u8 | 123u8 | 124u8 | 125u8 | 126u8 | 127u8 | 128u8 | 129u8 | 130u8 | 131u8 | 132u8 | 133u8 | 134u8 | 135u8 | 136u8 | 137u8 | 138u8 | 139u8 | 140u8 | 141u8 | 142u8 | 143u8 | 144u8 | 145u8 | 146u8 | 147u8 | 148u8 | 149u8 | 150u8 | 151u8,
     };
 
     // 24..=47 | 120..=127 | 152..=175 => true,
-    pub static IS_SAPPHIRE: OrderedSet<usize> = phf_ordered_set! {
-        24 | 25 | 26 | 27 | 28 | 29 | 30 | 31 | 32 | 33 | 34 | 35 | 36 | 37 | 38 | 39 | 40 | 41 | 42 | 43 | 44 | 45 | 46 | 47,
-        120 | 121 | 122 | 123 | 124 | 125 | 126 | 127,
-        152 | 153 | 154 | 155 | 156 | 157 | 158 | 159 | 160 | 161 | 162 | 163 | 164 | 165 | 166 | 167 | 168 | 169 | 170 | 171 | 172 | 173 | 174 | 175,
+    pub static IS_SAPPHIRE: OrderedSet<u8> = phf_ordered_set! {
+        24u8 | 25u8 | 26u8 | 27u8 | 28u8 | 29u8 | 30u8 | 31u8 | 32u8 | 33u8 | 34u8 | 35u8 | 36u8 | 37u8 | 38u8 | 39u8 | 40u8 | 41u8 | 42u8 | 43u8 | 44u8 | 45u8 | 46u8 | 47u8,
+        120u8 | 121u8 | 122u8 | 123u8 | 124u8 | 125u8 | 126u8 | 127u8,
+        152u8 | 153u8 | 154u8 | 155u8 | 156u8 | 157u8 | 158u8 | 159u8 | 160u8 | 161u8 | 162u8 | 163u8 | 164u8 | 165u8 | 166u8 | 167u8 | 168u8 | 169u8 | 170u8 | 171u8 | 172u8 | 173u8 | 174u8 | 175u8,
     };
 
     // 48..=71 | 128..=135 | 152..=159 | 176..=191,
-    pub static IS_RUBY: OrderedSet<usize> = phf_ordered_set! {
-        48 | 49 | 50 | 51 | 52 | 53 | 54 | 55 | 56 | 57 | 58 | 59 | 60 | 61 | 62 | 63 | 64 | 65 | 66 | 67 | 68 | 69 | 70 | 71,
-        128 | 129 | 130 | 131 | 132 | 133 | 134 | 135,
-        152 | 153 | 154 | 155 | 156 | 157 | 158 | 159,
-        176 | 177 | 178 | 179 | 180 | 181 | 182 | 183 | 184 | 185 | 186 | 187 | 188 | 189 | 190 | 191,
+    pub static IS_RUBY: OrderedSet<u8> = phf_ordered_set! {
+        48u8 | 49u8 | 50u8 | 51u8 | 52u8 | 53u8 | 54u8 | 55u8 | 56u8 | 57u8 | 58u8 | 59u8 | 60u8 | 61u8 | 62u8 | 63u8 | 64u8 | 65u8 | 66u8 | 67u8 | 68u8 | 69u8 | 70u8 | 71u8,
+        128u8 | 129u8 | 130u8 | 131u8 | 132u8 | 133u8 | 134u8 | 135u8,
+        152u8 | 153u8 | 154u8 | 155u8 | 156u8 | 157u8 | 158u8 | 159u8,
+        176u8 | 177u8 | 178u8 | 179u8 | 180u8 | 181u8 | 182u8 | 183u8 | 184u8 | 185u8 | 186u8 | 187u8 | 188u8 | 189u8 | 190u8 | 191u8,
     };
 
     // 72..=95 | 136..=143 | 160..=167 | 176..=183 | 192..=199,
-    pub static IS_GARNET: OrderedSet<usize> = phf_ordered_set! {
-        72 | 73 | 74 | 75 | 76 | 77 | 78 | 79 | 80 | 81 | 82 | 83 | 84 | 85 | 86 | 87 | 88 | 89 | 90 | 91 | 92 | 93 | 94 | 95,
-        136 | 137 | 138 | 139 | 140 | 141 | 142 | 143,
-        160 | 161 | 162 | 163 | 164 | 165 | 166 | 167,
-        176 | 177 | 178 | 179 | 180 | 181 | 182 | 183,
-        192 | 193 | 194 | 195 | 196 | 197 | 198 | 199,
+    pub static IS_GARNET: OrderedSet<u8> = phf_ordered_set! {
+        72u8 | 73u8 | 74u8 | 75u8 | 76u8 | 77u8 | 78u8 | 79u8 | 80u8 | 81u8 | 82u8 | 83u8 | 84u8 | 85u8 | 86u8 | 87u8 | 88u8 | 89u8 | 90u8 | 91u8 | 92u8 | 93u8 | 94u8 | 95u8,
+        136u8 | 137u8 | 138u8 | 139u8 | 140u8 | 141u8 | 142u8 | 143u8,
+        160u8 | 161u8 | 162u8 | 163u8 | 164u8 | 165u8 | 166u8 | 167u8,
+        176u8 | 177u8 | 178u8 | 179u8 | 180u8 | 181u8 | 182u8 | 183u8,
+        192u8 | 193u8 | 194u8 | 195u8 | 196u8 | 197u8 | 198u8 | 199u8,
     };
 
     // 96..=119 | 144..=151 | 168..=175 | 184..=199,
-    pub static IS_EMERALD: OrderedSet<usize> = phf_ordered_set! {
-        96 | 97 | 98 | 99 | 100 | 101 | 102 | 103 | 104 | 105 | 106 | 107 | 108 | 109 | 110 | 111 | 112 | 113 | 114 | 115 | 116 | 117 | 118 | 119,
-        144 | 145 | 146 | 147 | 148 | 149 | 150 | 151,
-        168 | 169 | 170 | 171 | 172 | 173 | 174 | 175,
-        184 | 185 | 186 | 187 | 188 | 189 | 190 | 191 | 192 | 193 | 194 | 195 | 196 | 197 | 198 | 199,
+    pub static IS_EMERALD: OrderedSet<u8> = phf_ordered_set! {
+        96u8 | 97u8 | 98u8 | 99u8 | 100u8 | 101u8 | 102u8 | 103u8 | 104u8 | 105u8 | 106u8 | 107u8 | 108u8 | 109u8 | 110u8 | 111u8 | 112u8 | 113u8 | 114u8 | 115u8 | 116u8 | 117u8 | 118u8 | 119u8,
+        144u8 | 145u8 | 146u8 | 147u8 | 148u8 | 149u8 | 150u8 | 151u8,
+        168u8 | 169u8 | 170u8 | 171u8 | 172u8 | 173u8 | 174u8 | 175u8,
+        184u8 | 185u8 | 186u8 | 187u8 | 188u8 | 189u8 | 190u8 | 191u8 | 192u8 | 193u8 | 194u8 | 195u8 | 196u8 | 197u8 | 198u8 | 199u8,
     };
 
-    pub static NOT_IN_LAST_SPHERES: OrderedMap<u32, usize> = phf_ordered_map! {
-        70 => 2, // topaz charm
-        93 | 96 | 100 => 1,// silver coin, butterfly ocarina and blue rose
+    pub static NOT_IN_LAST_SPHERES: OrderedMap<u8, usize> = phf_ordered_map! {
+        70u8 => 2, // topaz charm
+        93u8 | 96u8 | 100u8 => 1,// silver coin, butterfly ocarina and blue rose
     };
 
     pub static ITEM_NAMES: OrderedSet<&'static str> = phf_ordered_set! {
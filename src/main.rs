@@ -1,35 +1,115 @@
+mod advisor; //odds of what's left in a run already underway
+mod bag; //the bag of treasurespheres a game draws from
+mod binary; //packed binary run format
+mod compare; //goodness-of-fit of observed runs against the simulator
+mod compress; //gzip and zstd files
+mod convert; //binary run files to csv or json
+mod cooccurrence; //items found together in the same run
+mod draft; //splitting loot among players
+mod error; //typed errors of the generator and writer
+mod filter; //--filter predicate language
+mod histogram; //distributions of per-run quantities
+mod ids; //validated item, position and player count types
+mod itemset; //bitset of items for the generator
 mod loot; //phf hashmaps and Vanilla game constants
+mod metadata; //metadata written next to output files
+mod observed; //importing runs observed in the game
+mod planner; //odds of completing a target loadout
+mod reader; //reading back csv files
+mod rules; //challenge rules on top of the item pools
+mod serve; //local http server answering with json
+mod stats; //item and treasuresphere statistics over many runs
+mod sweep; //statistics over a grid of settings
+mod tui; //stepping through a run in the terminal
+mod unlocks; //unlock profiles filtering the item pools
+mod validate; //checking csv files against the game rules
 mod writer; //writing to wtr functions
-use anyhow::{Error, Result};
-use clap::Parser;
+use anyhow::{bail, Error, Result};
+use bag::{Bag, BAG_MAX};
+use clap::{Parser, Subcommand};
+use compress::Compression;
 use csv::Writer;
+use draft::{Drafter, Policy};
+use error::SimError;
+use filter::Filter;
+use ids::{ItemId, PlayerCount, SpherePos};
+use itemset::ItemSet;
+use loot::player_loot::PlayerMix;
 use loot::treasuresphere::Colors as Treasuresphere; // The treasuresphere types, i.e normal{1,2,3}, ruby, garnet
-use loot::{IT_COUNT, TS_COUNT}; // vanilla constants for item count and ts count in 1.4.5
+use loot::{IT_FOUND_MAX_N, IT_N, TS_COUNT}; // vanilla constant for ts count in 1.4.5
 use rand::{self, seq::SliceRandom, SeedableRng};
 use rand_chacha::ChaCha8Rng; // Useful for deterministic RNG
-use rayon;
 use rayon::prelude::*;
-use std::fs::File;
+use rules::{ItemPools, Rules};
+use serde_json::json;
 use std::io::Write;
+use std::ops::Range;
+use std::sync::LazyLock;
+use unlocks::Unlocks;
+use writer::OutputFormat;
+
+/// Runs generated and serialized together on a rayon worker
+const CHUNK_RUNS: usize = 1024;
+
+/// Version of the mapping from a seed to its runs
+///
+/// Bump it whenever a seed stops generating the same output, and regenerate
+/// the golden files in tests/golden for the new version.
+pub const GENERATION_VERSION: u32 = 1;
+
+/// Shown by `--version`, with the generation algorithm version
+static VERSION: LazyLock<String> = LazyLock::new(|| {
+    format!(
+        "{} (generation algorithm {})",
+        env!("CARGO_PKG_VERSION"),
+        GENERATION_VERSION
+    )
+});
 
 /// Program that simulates a number of games in Rabbit & Steel and writes items found
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version = VERSION.as_str(), about, long_about = None, args_conflicts_with_subcommands = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Number of game runs (samples)
     #[arg(short = 'n', long, default_value_t = 1, value_parser(clap::value_parser!(u64).range(1..=200000)))]
     //200k
     run_count: u64,
 
-    /// Player count
-    #[arg(short, long, default_value_t = 1, value_parser(clap::value_parser!(u64).range(1..=4)))]
-    player_count: u64,
+    /// Player count, or a mix of them as `1,2,4` or weighted `1:0.4,2:0.3,4:0.3`
+    #[arg(short, long, default_value = "1")]
+    player_count: PlayerMix,
+
+    #[command(flatten)]
+    pool: PoolArgs,
+
+    /// Split the loot among players with a picking policy, adds loadout_{0..=3} columns
+    #[arg(long, value_enum)]
+    draft: Option<Policy>,
+
+    /// Ranked item list (one per line) for the priority policy, repeat once per player
+    #[arg(long)]
+    priority: Vec<String>,
+
+    /// Only write runs matching an expression, i.e. "has(it_opal_necklace) and set(night) >= 3"
+    #[arg(short, long)]
+    filter: Option<String>,
 
     /// Output file (csv), if not used, print to stdout
     #[arg(short, long)]
     output_file: Option<String>,
 
-    /// Use a positive interger (u64) seed for RNG (non-compliant)
+    /// Output format, binary packs every run in a fixed-size record (see `convert`)
+    #[arg(long, value_enum, default_value_t = OutputFormat::Csv)]
+    format: OutputFormat,
+
+    /// Compress the output, picked by the file extension (.gz, .zst) if not used
+    #[arg(long, value_enum)]
+    compress: Option<Compression>,
+
+    /// Use a positive integer (u64) seed for RNG (non-compliant)
     #[arg(short, long)]
     seed: Option<u64>,
 
@@ -48,45 +128,303 @@ struct Args {
     // relative_headers: bool,
 }
 
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Probability of completing a target loadout by every treasuresphere
+    Plan(planner::PlanArgs),
+    /// Odds of the treasurespheres and items left, given the ones already seen in a run
+    Advise(advisor::AdviseArgs),
+    /// Item statistics for every combination of player counts, unlocks and rules
+    Sweep(sweep::SweepArgs),
+    /// Check csv files in this program's layout against the game rules
+    Validate(validate::ValidateArgs),
+    /// Test observed runs (csv or json) against the simulator and flag significant deviations
+    Compare(compare::CompareArgs),
+    /// How often each pair of items is found in the same run, and the lift over chance
+    Cooccurrence(cooccurrence::CooccurrenceArgs),
+    /// Distributions of per-run quantities, like the number of sets touched
+    Histogram(histogram::HistogramArgs),
+    /// Step through a run one treasuresphere at a time, showing the candidates and rejections
+    Tui(tui::TuiArgs),
+    /// Local HTTP server answering simulations, statistics, filter odds and replays with json
+    Serve(serve::ServeArgs),
+    /// Convert a binary run file to csv or json
+    Convert(convert::ConvertArgs),
+}
+
+// Options shaping the item pools, shared with the subcommands
+#[derive(clap::Args, Debug)]
+pub struct PoolArgs {
+    /// Unlock profile, `all` or a file listing unlocked items per line
+    #[arg(short, long, default_value = "all")]
+    pub unlocks: String,
+
+    /// Rules file with bans, forced items, set-only restrictions and position limits
+    #[arg(short, long)]
+    pub rules: Option<String>,
+}
+
+impl PoolArgs {
+    /// Reads the unlock profile and rules file into the item pools
+    pub fn load(&self) -> Result<ItemPools, Error> {
+        let unlocks = Unlocks::load(&self.unlocks)?;
+        let rules = match &self.rules {
+            Some(path) => Rules::from_file(path)?,
+            None => Rules::default(),
+        };
+        Ok(ItemPools::new(&unlocks, rules))
+    }
+}
+
+// Options of the subcommands simulating runs per player count, shared like PoolArgs
+#[derive(clap::Args, Debug)]
+pub struct SimArgs {
+    /// Number of game runs simulated per player count
+    #[arg(short = 'n', long, default_value_t = 10000, value_parser(clap::value_parser!(u64).range(1..=200000)))]
+    pub run_count: u64,
+
+    /// Player counts to simulate, joined by ','
+    #[arg(short, long, value_delimiter = ',', default_values_t = PlayerCount::all())]
+    pub player_count: Vec<PlayerCount>,
+
+    /// Use a positive integer (u64) seed for RNG (non-compliant)
+    #[arg(short, long)]
+    pub seed: Option<u64>,
+}
+
 fn main() -> Result<(), Error> {
     let args = Args::parse();
+    match &args.command {
+        Some(Command::Plan(plan_args)) => return planner::run(plan_args),
+        Some(Command::Advise(advise_args)) => return advisor::run(advise_args),
+        Some(Command::Sweep(sweep_args)) => return sweep::run(sweep_args),
+        Some(Command::Validate(validate_args)) => return validate::run(validate_args),
+        Some(Command::Compare(compare_args)) => return compare::run(compare_args),
+        Some(Command::Cooccurrence(cooccurrence_args)) => {
+            return cooccurrence::run(cooccurrence_args)
+        }
+        Some(Command::Histogram(histogram_args)) => return histogram::run(histogram_args),
+        Some(Command::Tui(tui_args)) => return tui::run(tui_args),
+        Some(Command::Serve(serve_args)) => return serve::run(serve_args),
+        Some(Command::Convert(convert_args)) => return convert::run(convert_args),
+        None => {}
+    }
+
     let game_count = args.run_count as usize;
-    let player_count = args.player_count as usize;
+    let player_mix = &args.player_count;
+    let pools = args.pool.load()?;
+    let drafter = match args.draft {
+        Some(policy) => Some(Drafter::new(policy, &args.priority)?),
+        None if !args.priority.is_empty() => {
+            bail!("--priority files are only read with --draft priority")
+        }
+        None => None,
+    };
+    let filter = match &args.filter {
+        Some(source) => Some(Filter::parse(source)?),
+        None => None,
+    };
+    if args.format == OutputFormat::Binary && drafter.is_some() {
+        bail!("The binary format doesn't hold loadouts, use csv with --draft");
+    }
 
-    let mut wtr = Writer::from_writer(Vec::with_capacity(game_count));
-    writer::field_wtr_headers(&mut wtr, &false, &player_count)?;
+    let mut out = compress::create(&args.output_file, args.compress)?;
+    match args.format {
+        OutputFormat::Csv => {
+            let mut wtr = Writer::from_writer(Vec::new());
+            let header_player_count = player_mix.player_counts()[0]; // absolute headers fit any count
+            writer::field_wtr_headers(&mut wtr, &false, &header_player_count, &drafter.is_some())?;
+            out.write_all(&wtr.into_inner()?)?;
+        }
+        OutputFormat::Binary => {
+            let player_count = match player_mix.player_counts()[..] {
+                [player_count] => Some(player_count),
+                _ => None,
+            };
+            let header = binary::Header::new(args.seed, player_count);
+            binary::write_header(&mut out, &header)?;
+        }
+    }
 
-    // My longest step is running generate_{ts, it}, so
-    // it's fine leaving Writer struct on single thread
+    // Runs are generated and written to csv in chunks on the rayon workers,
+    // a batch of chunks at a time so memory stays flat, then written in order
     //
     // (mutable references to outside objects are bad with rayon)
-    let data: Vec<(Vec<Treasuresphere>, Vec<usize>)> = (0..game_count)
-        .into_par_iter()
-        .map(|i| {
-            let mut seed = match args.seed {
-                Some(val) => ChaCha8Rng::seed_from_u64(val),
-                None => ChaCha8Rng::from_os_rng(),
-            };
-            seed.set_stream(i as u64); // Makes the seed deterministic despite threads
+    let serialize_chunk = |chunk: Range<usize>| -> Result<(Vec<u8>, usize), Error> {
+        let mut wtr = Writer::from_writer(Vec::with_capacity(chunk.len() * 512));
+        let mut records = Vec::new();
+        let mut matched = 0;
+        for i in chunk {
+            let mut seed = run_rng(&args.seed, &i);
+            let player_count = player_mix.pick(&mut seed);
+            let loot_counts = loot::player_loot::loot_counts(player_count);
             let ts: Vec<Treasuresphere> = generate_ts(&mut seed);
-            let it: Vec<usize> = generate_it(&ts, &mut seed, &player_count).unwrap();
-            (ts, it)
-        })
+            let it: Vec<ItemId> = generate_it(&ts, &mut seed, &player_count, &pools)?;
+            if let Some(filter) = &filter {
+                let run = filter::RunView {
+                    ts: &ts,
+                    items: &it,
+                    loot_counts: &loot_counts,
+                };
+                if !filter.matches(&run) {
+                    continue;
+                }
+            }
+            // Drafting rolls after the items, so the same seed keeps the same loot
+            let loadouts = drafter
+                .as_ref()
+                .map(|d| d.draft(&it, &loot_counts, &player_count, &mut seed))
+                .transpose()?;
+            match args.format {
+                OutputFormat::Csv => writer::field_wtr(
+                    &mut wtr,
+                    &ts,
+                    &it,
+                    &false,
+                    &player_count,
+                    loadouts.as_deref(),
+                )?,
+                OutputFormat::Binary => {
+                    binary::encode_run(&mut records, &player_count, &ts, &it, &loot_counts)?
+                }
+            }
+            matched += 1;
+        }
+        match args.format {
+            OutputFormat::Csv => Ok((wtr.into_inner()?, matched)),
+            OutputFormat::Binary => Ok((records, matched)),
+        }
+    };
+
+    let chunks: Vec<Range<usize>> = (0..game_count)
+        .step_by(CHUNK_RUNS)
+        .map(|start| start..(start + CHUNK_RUNS).min(game_count))
         .collect();
+    let batch_size = rayon::current_num_threads() * 4;
+    let mut matched_count = 0;
+    for batch in chunks.chunks(batch_size) {
+        let serialized: Vec<(Vec<u8>, usize)> = batch
+            .par_iter()
+            .map(|chunk| serialize_chunk(chunk.clone()))
+            .collect::<Result<_, Error>>()?;
+        for (bytes, matched) in serialized {
+            out.write_all(&bytes)?;
+            matched_count += matched;
+        }
+    }
 
-    data.iter()
-        .for_each(|(t, i)| writer::field_wtr(&mut wtr, &t, &i, &false, &player_count).unwrap());
+    if let Some(filter) = &filter {
+        eprintln!(
+            "{} of {} runs matched the filter: {}",
+            matched_count,
+            game_count,
+            filter.source()
+        );
+    }
+
+    if args.output_file.is_none() && args.format == OutputFormat::Csv {
+        writeln!(out)?;
+    }
+    out.finish()?;
 
-    if let Some(file) = args.output_file {
-        let mut file = File::create(file)?;
-        file.write_all(&wtr.into_inner()?)?;
-    } else {
-        println!("{}", String::from_utf8(wtr.into_inner()?)?);
+    if let Some(path) = &args.output_file {
+        metadata::write_metadata(
+            path,
+            &json!({
+                "version": env!("CARGO_PKG_VERSION"),
+                "generation_version": GENERATION_VERSION,
+                "run_count": game_count,
+                "player_count": player_mix.to_string(),
+                "seed": args.seed,
+                "unlocks": args.pool.unlocks,
+                "rules_file": args.pool.rules,
+                "rules": pools.rules().describe(),
+                "draft": args.draft.map(|x| x.to_string()),
+                "priority": args.priority,
+                "filter": args.filter,
+                "matched_count": matched_count,
+                "format": args.format.to_string(),
+                "compression": args.compress.unwrap_or(Compression::from_path(path)).to_string(),
+            }),
+        )?;
     }
 
     Ok(())
 }
 
+/// RNG of the i-th run, deterministic for a seed no matter the thread it runs on
+pub fn run_rng(seed: &Option<u64>, i: &usize) -> ChaCha8Rng {
+    let mut rng = match seed {
+        Some(val) => ChaCha8Rng::seed_from_u64(*val),
+        None => ChaCha8Rng::from_os_rng(),
+    };
+    rng.set_stream(*i as u64); // Makes the seed deterministic despite threads
+    rng
+}
+
+/// Settings every run of a simulation rolls with
+#[derive(Debug, Clone, Copy)]
+pub struct RunSettings<'a> {
+    pub seed: Option<u64>,
+    pub player_count: PlayerCount,
+    pub pools: &'a ItemPools,
+    pub bag: &'a Bag,
+    pub seen_ts: &'a [Treasuresphere], // already seen, see `generate_ts_after`
+    pub seen_it: &'a [Vec<ItemId>],    // already seen, see `generate_it_after`
+}
+
+impl<'a> RunSettings<'a> {
+    /// Settings of runs rolled from the start with the game's bag, nothing seen
+    pub fn new(seed: Option<u64>, player_count: PlayerCount, pools: &'a ItemPools) -> Self {
+        RunSettings {
+            seed,
+            player_count,
+            pools,
+            bag: Bag::standard(),
+            seen_ts: &[],
+            seen_it: &[],
+        }
+    }
+}
+
+/// Rolls the i-th run, the same for a seed as row i of a generated file
+pub fn generate_run(
+    i: usize,
+    settings: &RunSettings,
+) -> Result<(Vec<Treasuresphere>, Vec<ItemId>), SimError> {
+    let mut rng = run_rng(&settings.seed, &i);
+    let ts = generate_ts_after(settings.seen_ts, settings.bag, &mut rng);
+    let it = generate_it_after(
+        &ts,
+        settings.seen_it,
+        &mut rng,
+        &settings.player_count,
+        settings.pools,
+    )?;
+    Ok((ts, it))
+}
+
+/// Simulates `game_count` runs on the rayon workers without keeping them
+///
+/// Every worker folds its runs into an accumulator made by `init`, then the
+/// accumulators are merged, in run order.
+pub fn simulate_runs<T: Send>(
+    game_count: &usize,
+    settings: &RunSettings,
+    init: impl Fn() -> T + Sync + Send,
+    fold: impl Fn(T, &[Treasuresphere], &[ItemId]) -> T + Sync + Send,
+    merge: impl Fn(T, T) -> T + Sync + Send,
+) -> Result<T, Error> {
+    (0..*game_count)
+        .into_par_iter()
+        .map(|i| generate_run(i, settings))
+        .try_fold(&init, |acc, run| {
+            let (ts, it) = run?;
+            Ok::<_, Error>(fold(acc, &ts, &it))
+        })
+        .try_reduce(&init, |a, b| Ok(merge(a, b)))
+}
+
 /// Generates a set of 6 random treasurespheres per game
 ///
 /// # Examples
@@ -99,17 +437,37 @@ fn main() -> Result<(), Error> {
 /// assert_eq!(ts.len(), 6)
 /// assert_eq!(ts.get(0) == Some<Colors>);
 /// ```
-pub fn generate_ts(mut seed: &mut ChaCha8Rng) -> Vec<Treasuresphere> {
+pub fn generate_ts(seed: &mut ChaCha8Rng) -> Vec<Treasuresphere> {
+    generate_ts_after(&[], Bag::standard(), seed)
+}
+
+/// Generates the treasurespheres left in `bag` after the ones already seen
+///
+/// The seen treasurespheres are taken out of the bag before shuffling, with
+/// nothing seen and the game's bag this rolls exactly like `generate_ts`.
+pub fn generate_ts_after(
+    seen: &[Treasuresphere],
+    from: &Bag,
+    mut seed: &mut ChaCha8Rng,
+) -> Vec<Treasuresphere> {
     let count = *TS_COUNT;
     let mut ts = Vec::with_capacity(count);
+    ts.extend_from_slice(seen);
 
-    let mut nums: Vec<u8> = (0..8).collect();
-    nums.shuffle(&mut seed);
-    for i in 0..count {
-        ts.push(Treasuresphere::from_index(nums[i]));
+    // The bag, on the stack, minus the ones seen
+    let mut len = from.spheres().len();
+    let mut bag = [Treasuresphere::Normal; BAG_MAX];
+    bag[..len].copy_from_slice(from.spheres());
+    for t in seen {
+        if let Some(i) = bag[..len].iter().position(|x| x == t) {
+            bag.copy_within(i + 1..len, i);
+            len -= 1;
+        }
     }
+    bag[..len].shuffle(&mut seed);
+    ts.extend(bag[..len].iter().take(count.saturating_sub(seen.len())));
 
-    return ts;
+    ts
 }
 
 /// Generates a set of random items per game
@@ -118,70 +476,375 @@ pub fn generate_ts(mut seed: &mut ChaCha8Rng) -> Vec<Treasuresphere> {
 /// and are deemed "relative", i.e.:
 /// - in 1P, items 4_2 [18] and 5_0 [19] will sit next to each other, where items 4_{3,4} are not evaulated
 /// - in 4p, items 4_4 [24] and 5_0 [25] next to each other
-#[allow(unused_variables)]
-fn generate_it(
-    ts: &Vec<Treasuresphere>,
+pub fn generate_it(
+    ts: &[Treasuresphere],
+    seed: &mut ChaCha8Rng,
+    player_count: &PlayerCount,
+    pools: &ItemPools,
+) -> Result<Vec<ItemId>, SimError> {
+    generate_it_after(ts, &[], seed, player_count, pools)
+}
+
+/// Candidates of a treasuresphere after the partial shuffle, and what became of them
+///
+/// Held in fixed-size arrays, so rolling a treasuresphere never allocates.
+#[derive(Debug, Clone)]
+pub struct SphereRoll {
+    pool: [ItemId; IT_N], // unlocked items allowed by the rules, the candidates shuffled to the back
+    pool_size: usize,
+    shuffled_from: usize,
+    pub looked: usize, // candidates looked at, the ones not chosen were rejected
+    chosen: [ItemId; IT_FOUND_MAX_N],
+    chosen_count: usize,
+}
+
+impl SphereRoll {
+    /// Unlocked items allowed by the rules, before shuffling
+    pub fn pool_size(&self) -> usize {
+        self.pool_size
+    }
+
+    /// Candidates in shuffled order
+    pub fn candidates(&self) -> &[ItemId] {
+        &self.pool[self.shuffled_from..self.pool_size]
+    }
+
+    /// Items chosen, in the order they were found
+    pub fn chosen(&self) -> &[ItemId] {
+        &self.chosen[..self.chosen_count]
+    }
+
+    /// Looks at candidates past the ones already looked at until `roll_count` are chosen
+    fn choose(&mut self, roll_count: usize, items_found: &ItemSet, t: &SpherePos, rules: &Rules) {
+        let start = self.shuffled_from + self.looked;
+        for p in start..self.pool_size {
+            if self.chosen_count == roll_count {
+                break;
+            }
+            let item = self.pool[p];
+            self.looked += 1;
+            if !items_found.contains(&item) && rules.is_item_in_ts_pos(&item, t) {
+                self.chosen[self.chosen_count] = item;
+                self.chosen_count += 1;
+            }
+        }
+    }
+}
+
+/// Rolls `roll_count` items of the t-th treasuresphere, skipping the ones already found
+/// and the ones not allowed in its position
+///
+/// Only the first `shuffle_count` items of the pool are shuffled in. If they run out
+/// before `roll_count` are chosen, the rest of the pool is shuffled in after them, so
+/// `chosen` is only short when the whole pool runs out.
+pub fn roll_sphere(
+    ts_t: &Treasuresphere,
+    t: &SpherePos,
+    shuffle_count: usize,
+    roll_count: usize,
+    items_found: &ItemSet,
     mut seed: &mut ChaCha8Rng,
-    player_count: &usize,
-) -> Result<Vec<usize>, Error> {
-    let loot_counts = loot::player_loot::loot_counts(*player_count)?; // n loot to roll every ts
-    let loot_sum = loot::player_loot::loot_sum(*player_count)?; // sum of loot rolled in game
-
-    let mut items_found: Vec<usize> = Vec::with_capacity(loot_sum); //collection of loot in game
-
-    for t in 0..*TS_COUNT {
-        let ts_t = ts.get(t).expect("Invalid treasuresphere indexed.");
-        let loot_count = loot_counts
-            .get(t)
-            .expect("ts indexed loot_counts out of bounds in generate_it()");
-        let mut p: usize = 0; // Count through item indices in "Pool" of total itempool
-
-        // [QoL] orders items per ts by their index by buffering it
-        let mut items_found_t: Vec<usize> = Vec::with_capacity(*loot::IT_FOUND_MAX_PER_TS);
-
-        // Pull items in itempool and partially shuffle them
-        let mut itempool = ts_t.items_in_ts();
-        let itempool_slice = itempool
-            .partial_shuffle(&mut seed, loot_count + items_found.len())
-            .0;
-
-        'roll_next_item: for i in 0..*loot_count {
-            'find_valid_item: while p < *IT_COUNT {
-                let item = itempool_slice
-                    .get(p)
-                    .expect("Failed index on item in pool.");
-                if !items_found.contains(item)
-                    && loot::treasuresphere::is_item_in_ts_pos(item, &t, TS_COUNT)
-                {
-                    items_found_t.push(
-                        *itempool_slice
-                            .get(p)
-                            .expect("items_found_t.push() failed in generate_it()"),
-                    );
-                    p += 1;
-                    continue 'roll_next_item; // advances to next item
-                } else {
-                    p += 1;
-                    continue 'find_valid_item;
+    pools: &ItemPools,
+) -> SphereRoll {
+    let mut roll = SphereRoll {
+        pool: [ItemId::default(); IT_N],
+        pool_size: 0,
+        shuffled_from: 0,
+        looked: 0,
+        chosen: [ItemId::default(); IT_FOUND_MAX_N],
+        chosen_count: 0,
+    };
+
+    // Copy the unlocked items allowed by the rules and partially shuffle them
+    let pool = pools.get(ts_t);
+    roll.pool_size = pool.len();
+    roll.pool[..roll.pool_size].copy_from_slice(pool);
+    let itempool_slice = roll.pool[..roll.pool_size]
+        .partial_shuffle(&mut seed, shuffle_count)
+        .0;
+    roll.shuffled_from = roll.pool_size - itempool_slice.len();
+
+    let roll_count = roll_count.min(IT_FOUND_MAX_N);
+    roll.choose(roll_count, items_found, t, pools.rules());
+
+    // Rarely the shuffled items are all found or limited, the rest of the pool goes after
+    // them in shuffled order. It never rolls otherwise, keeping seeds as they were.
+    if roll.chosen_count < roll_count && roll.shuffled_from > 0 {
+        let rest = roll.shuffled_from;
+        roll.pool[..rest].shuffle(&mut seed);
+        roll.pool[..roll.pool_size].rotate_left(rest);
+        roll.shuffled_from = 0;
+        roll.choose(roll_count, items_found, t, pools.rules());
+    }
+    roll
+}
+
+/// Generates the items per game after the ones already seen
+///
+/// `seen` holds the items already seen in the first treasurespheres, they count
+/// towards each treasuresphere's loot and are never rolled again, not even in the
+/// slots left in a treasuresphere before the one they were seen in. With nothing
+/// seen this rolls exactly like `generate_it`.
+pub fn generate_it_after(
+    ts: &[Treasuresphere],
+    seen: &[Vec<ItemId>],
+    seed: &mut ChaCha8Rng,
+    player_count: &PlayerCount,
+    pools: &ItemPools,
+) -> Result<Vec<ItemId>, SimError> {
+    let loot_counts = loot::player_loot::loot_table(*player_count); // n loot to roll every ts
+    let loot_sum = loot::player_loot::loot_sum(*player_count); // sum of loot rolled in game
+
+    let mut items_found: Vec<ItemId> = Vec::with_capacity(loot_sum); //collection of loot in game
+    let mut found = ItemSet::default(); // the same items and every item seen, for duplicate checks
+    found.extend(seen.iter().flatten());
+
+    for (t, loot_count) in SpherePos::all().zip(loot_counts) {
+        let ts_t = ts.get(t.index()).ok_or(SimError::IndexOutOfRange {
+            what: "treasuresphere",
+            index: t.index(),
+            len: ts.len(),
+        })?;
+
+        // Items already seen in this ts are found, only the rest is rolled
+        let found_before_t = items_found.len();
+        let seen_t: &[ItemId] = seen.get(t.index()).map_or(&[], |x| x.as_slice());
+        items_found.extend_from_slice(seen_t);
+        let roll_count = loot_count.saturating_sub(seen_t.len());
+
+        let roll = roll_sphere(
+            ts_t,
+            &t,
+            loot_count + items_found.len(),
+            roll_count,
+            &found,
+            seed,
+            pools,
+        );
+        if roll.chosen().len() < roll_count {
+            return Err(SimError::PoolExhausted {
+                color: *ts_t,
+                position: t,
+            });
+        }
+        items_found.extend_from_slice(roll.chosen());
+        found.extend(roll.chosen());
+        // [QoL] orders items per ts by their index
+        items_found[found_before_t..].sort_unstable();
+    }
+
+    Ok(items_found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use loot::sets;
+    use loot::treasuresphere::is_item_in_ts_pos;
+    use proptest::prelude::*;
+
+    /// Rules keeping only the items of the given sets in every pool
+    fn only_sets(sets: &[usize]) -> Rules {
+        Rules {
+            only_sets: sets.to_vec(),
+            ..Rules::default()
+        }
+    }
+
+    fn items(indices: &[usize]) -> Vec<ItemId> {
+        indices.iter().map(|x| ItemId::new(*x).unwrap()).collect()
+    }
+
+    fn one_player() -> PlayerCount {
+        PlayerCount::new(1).unwrap()
+    }
+
+    fn first() -> SpherePos {
+        SpherePos::new(0).unwrap()
+    }
+
+    #[test]
+    fn roll_sphere_shuffles_in_the_rest_of_the_pool() {
+        let rules = only_sets(&[0]);
+        let mut found = ItemSet::default();
+        found.extend(&items(&[0, 1, 2, 3, 4, 5]));
+        for i in 0..200 {
+            let mut rng = run_rng(&Some(7), &i);
+            // One item shuffled in, two to choose: only items 6 and 7 are left
+            let roll = roll_sphere(
+                &Treasuresphere::Normal,
+                &first(),
+                1,
+                2,
+                &found,
+                &mut rng,
+                &ItemPools::new(&Unlocks::all(), rules.clone()),
+            );
+            let mut chosen = roll.chosen().to_vec();
+            chosen.sort_unstable();
+            assert_eq!(chosen, items(&[6, 7]));
+            assert_eq!(roll.candidates().len(), roll.pool_size());
+        }
+    }
+
+    #[test]
+    fn roll_sphere_is_short_when_the_pool_runs_out() {
+        let rules = only_sets(&[0]);
+        let mut found = ItemSet::default();
+        found.extend(&items(&[0, 1, 2, 3, 4, 5, 6]));
+        let mut rng = run_rng(&Some(7), &0);
+        let roll = roll_sphere(
+            &Treasuresphere::Normal,
+            &first(),
+            1,
+            3,
+            &found,
+            &mut rng,
+            &ItemPools::new(&Unlocks::all(), rules.clone()),
+        );
+        assert_eq!(roll.chosen(), items(&[7]));
+    }
+
+    #[test]
+    fn generate_it_draws_every_item_despite_position_limits() {
+        // 48 items, two thirds of them limited out of the last treasuresphere, so its
+        // shuffled items often run out before the pool does
+        let mut rules = only_sets(&[0, 1, 2, 3, 4, 5]);
+        rules.limits = (0..4)
+            .flat_map(|x| sets::items_in_set(&x))
+            .map(|x| (x, 1))
+            .collect();
+        let ts = vec![Treasuresphere::Normal; *TS_COUNT];
+        let loot_counts = loot::player_loot::loot_table(one_player());
+        for i in 0..500 {
+            let mut rng = run_rng(&Some(3), &i);
+            let items = generate_it(
+                &ts,
+                &mut rng,
+                &one_player(),
+                &ItemPools::new(&Unlocks::all(), rules.clone()),
+            )
+            .unwrap();
+            assert_eq!(items.len(), loot_counts.iter().sum::<usize>());
+
+            let mut distinct = items.clone();
+            distinct.sort_unstable();
+            distinct.dedup();
+            assert_eq!(distinct.len(), items.len());
+
+            let mut start = 0;
+            for (t, count) in SpherePos::all().zip(loot_counts) {
+                for item in &items[start..start + count] {
+                    assert!(sets::set_of(item) < 6);
+                    assert!(rules.is_item_in_ts_pos(item, &t));
                 }
+                start += count;
             }
         }
-        items_found_t.sort_unstable();
-        items_found.append(&mut items_found_t);
     }
 
-    return Ok(items_found);
+    #[test]
+    fn generate_ts_after_draws_only_from_its_bag() {
+        let bag = Bag::parse("ruby 4\nemerald 2\n").unwrap();
+        for i in 0..50 {
+            let mut ts = generate_ts_after(&[], &bag, &mut run_rng(&Some(1), &i));
+            ts.sort_by_key(|x| *x as usize);
+            assert_eq!(ts, bag.spheres());
+        }
+    }
+
+    #[test]
+    fn generate_it_after_never_rolls_items_seen_later() {
+        // Only one item seen in ts_0, its other slots must not roll the items seen in ts_1
+        let ts = generate_ts_after(
+            &[Treasuresphere::Normal, Treasuresphere::Opal],
+            Bag::standard(),
+            &mut run_rng(&Some(1), &0),
+        );
+        let later = items(&[120, 121, 122, 123, 124]);
+        let seen = vec![items(&[0]), later.clone()];
+        for i in 0..300 {
+            let mut rng = run_rng(&Some(1), &i);
+            let run = generate_it_after(
+                &ts,
+                &seen,
+                &mut rng,
+                &one_player(),
+                &ItemPools::new(&Unlocks::all(), Rules::default()),
+            )
+            .unwrap();
+            assert!(run[..5].iter().all(|x| !later.contains(x)), "{:?}", run);
+            let mut distinct = run.clone();
+            distinct.sort_unstable();
+            distinct.dedup();
+            assert_eq!(distinct.len(), run.len());
+        }
+    }
+
+    #[test]
+    fn generate_it_errors_when_the_pool_runs_out() {
+        // 8 items can't fill 22 slots, the second treasuresphere runs out
+        let ts = vec![Treasuresphere::Normal; *TS_COUNT];
+        let mut rng = run_rng(&Some(3), &0);
+        let result = generate_it(
+            &ts,
+            &mut rng,
+            &one_player(),
+            &ItemPools::new(&Unlocks::all(), only_sets(&[0])),
+        );
+        assert!(matches!(
+            result,
+            Err(SimError::PoolExhausted {
+                color: Treasuresphere::Normal,
+                position,
+            }) if position.index() == 1
+        ));
+    }
+
+    proptest! {
+        #[test]
+        fn runs_keep_the_loot_invariants(
+            seed in any::<u64>(),
+            run in 0..10_000usize,
+            player_count in prop::sample::select(PlayerCount::all().collect::<Vec<_>>()),
+        ) {
+            let mut rng = run_rng(&Some(seed), &run);
+            let ts = generate_ts(&mut rng);
+            let items = generate_it(
+                &ts,
+                &mut rng,
+                &player_count,
+                &ItemPools::new(&Unlocks::all(), Rules::default()),
+            )
+            .unwrap();
 
-    // let items_found_str: Vec<&'static str> = items_found
-    //     .iter()
-    //     .map(|x| {
-    //         *loot::treasuresphere::ITEM_NAMES
-    //             .get(x)
-    //             .expect("Index of item index to names in generate_it() misindexed")
-    //     })
-    //     .collect::<Vec<&str>>();
+            // Treasurespheres are drawn from the bag of 8 without replacement
+            prop_assert_eq!(ts.len(), *TS_COUNT);
+            for color in &ts {
+                let drawn = ts.iter().filter(|x| *x == color).count();
+                prop_assert!(drawn <= Bag::standard().spheres().iter().filter(|x| *x == color).count());
+            }
 
-    // assert_eq!(loot_sum, items_found_str.len()); // Simply checks if they're same length, should not fail
+            // The loot table's total, no item twice
+            let loot_sum = loot::player_loot::loot_sum(player_count);
+            prop_assert_eq!(items.len(), loot_sum);
+            let mut found = ItemSet::default();
+            for item in &items {
+                prop_assert!(!found.contains(item), "item {} found twice", item);
+                found.insert(item);
+            }
 
-    // return Ok(items_found_str);
+            // Each item in its treasuresphere's pool and allowed in its position
+            let loot_counts = loot::player_loot::loot_table(player_count);
+            let mut start = 0;
+            for (t, count) in SpherePos::all().zip(loot_counts) {
+                let color = ts[t.index()];
+                for item in &items[start..start + count] {
+                    prop_assert!(color.pool().contains(item), "{} not in the {} pool", item, color);
+                    prop_assert!(is_item_in_ts_pos(item, &t), "{} in position {}", item, t);
+                }
+                start += count;
+            }
+        }
+    }
 }
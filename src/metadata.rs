@@ -0,0 +1,18 @@
+// Module for the metadata file written next to an output file
+use anyhow::{Error, Result};
+use serde_json::Value;
+use std::fs::File;
+use std::io::Write;
+
+/// Path of the metadata file for an output file, i.e. runs.csv -> runs.csv.meta.json
+pub fn metadata_path(output_file: &str) -> String {
+    format!("{}.meta.json", output_file)
+}
+
+/// Writes the settings a dataset was generated with, so it can be reproduced later
+pub fn write_metadata(output_file: &str, meta: &Value) -> Result<(), Error> {
+    let mut file = File::create(metadata_path(output_file))?;
+    file.write_all(serde_json::to_string_pretty(meta)?.as_bytes())?;
+    file.write_all(b"\n")?;
+    Ok(())
+}
@@ -0,0 +1,269 @@
+// Module for importing runs observed in the real game
+//
+// Observed runs come as csv, the same layout the simulator writes, or as json:
+// [
+//   {
+//     "player_count": 2,
+//     "spheres": [
+//       { "color": "ruby", "items": ["it_topaz_charm", "it_flame_bow", ...] },
+//       ...
+//     ]
+//   }
+// ]
+// A run can stop early, spheres past the last one seen are left out (or left empty in csv).
+use crate::compress;
+use crate::ids::{ItemId, PlayerCount};
+use crate::loot;
+use crate::reader::{self, Layout};
+use crate::unlocks::parse_item;
+use anyhow::{bail, Context, Error, Result};
+use loot::treasuresphere::Colors as Treasuresphere;
+use serde_json::{json, Value};
+use std::io::Read;
+
+/// A run, possibly stopped early, as it was seen in the game
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObservedRun {
+    pub player_count: PlayerCount,
+    pub spheres: Vec<(Treasuresphere, Vec<ItemId>)>, // in order, with the items found in each
+}
+
+/// Reads observed runs, as json if the file ends in `.json` (before `.gz` or `.zst`),
+/// otherwise as csv
+pub fn read(path: &str) -> Result<Vec<ObservedRun>, Error> {
+    if compress::strip_extension(path).ends_with(".json") {
+        read_json(path)
+    } else {
+        read_csv(path)
+    }
+}
+
+fn read_csv(path: &str) -> Result<Vec<ObservedRun>, Error> {
+    let mut rdr = reader::open(path)?;
+    let layout = Layout::from_headers(rdr.headers()?)?;
+
+    let mut runs = Vec::new();
+    for (row, record) in rdr.records().enumerate() {
+        let record = record?;
+        let run: Result<ObservedRun, Error> = (|| {
+            let player_count = layout
+                .cell(&record, &Some(layout.player_count))
+                .parse::<usize>()
+                .context("Invalid player_count")?;
+
+            let mut spheres = Vec::new();
+            for (t, c) in layout.ts.iter().enumerate() {
+                let color = match layout.cell(&record, c) {
+                    "" => break, // the run stopped here
+                    cell => cell.parse::<Treasuresphere>()?,
+                };
+                let items = layout.it[t]
+                    .iter()
+                    .map(|c| layout.cell(&record, c))
+                    .filter(|x| !x.is_empty())
+                    .map(parse_item)
+                    .collect::<Result<Vec<ItemId>, Error>>()?;
+                spheres.push((color, items));
+            }
+
+            Ok(ObservedRun {
+                player_count: PlayerCount::new(player_count)?,
+                spheres,
+            })
+        })();
+        runs.push(run.with_context(|| format!("{}: row {}", path, row + 1))?);
+    }
+
+    Ok(runs)
+}
+
+fn read_json(path: &str) -> Result<Vec<ObservedRun>, Error> {
+    let mut text = String::new();
+    compress::open(path)?
+        .read_to_string(&mut text)
+        .with_context(|| format!("{}: could not read", path))?;
+    let json: Value =
+        serde_json::from_str(&text).with_context(|| format!("{}: invalid json", path))?;
+    let Some(entries) = json.as_array() else {
+        bail!("{}: expected a list of runs", path);
+    };
+
+    let mut runs = Vec::new();
+    for (r, entry) in entries.iter().enumerate() {
+        let run: Result<ObservedRun, Error> = (|| {
+            let Some(player_count) = entry["player_count"].as_u64() else {
+                bail!("Missing player_count");
+            };
+
+            let mut spheres = Vec::new();
+            for sphere in entry["spheres"].as_array().unwrap_or(&Vec::new()) {
+                let Some(color) = sphere["color"].as_str() else {
+                    bail!("Missing sphere color");
+                };
+                let items = sphere["items"]
+                    .as_array()
+                    .unwrap_or(&Vec::new())
+                    .iter()
+                    .map(|x| match x {
+                        Value::String(name) => parse_item(name),
+                        Value::Number(n) => parse_item(&n.to_string()),
+                        _ => bail!("Invalid item: {}", x),
+                    })
+                    .collect::<Result<Vec<ItemId>, Error>>()?;
+                spheres.push((color.parse()?, items));
+            }
+
+            Ok(ObservedRun {
+                player_count: PlayerCount::new(player_count as usize)?,
+                spheres,
+            })
+        })();
+        runs.push(run.with_context(|| format!("{}: run {}", path, r))?);
+    }
+
+    Ok(runs)
+}
+
+/// A run as json, in the schema read above
+pub fn run_json(
+    (ts, items): &(Vec<Treasuresphere>, Vec<ItemId>),
+    player_count: &PlayerCount,
+    loot_counts: &[usize],
+) -> Value {
+    let mut start = 0;
+    let spheres: Vec<Value> = ts
+        .iter()
+        .zip(loot_counts)
+        .map(|(color, count)| {
+            let names: Vec<&str> = items[start..start + count]
+                .iter()
+                .map(|i| i.name())
+                .collect();
+            start += count;
+            json!({ "color": color.to_string(), "items": names })
+        })
+        .collect();
+    json!({
+        "player_count": player_count.get(),
+        "spheres": spheres,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// Writes `text` to a file of its own in the temp directory, ending in `extension`
+    fn observed_file(name: &str, extension: &str, text: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "rns-loot-sim-{}-{}.{}",
+            name,
+            std::process::id(),
+            extension
+        ));
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    fn read_text(name: &str, extension: &str, text: &str) -> Result<Vec<ObservedRun>, Error> {
+        let path = observed_file(name, extension, text);
+        let runs = read(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+        runs
+    }
+
+    fn item(name: &str) -> ItemId {
+        parse_item(name).unwrap()
+    }
+
+    #[test]
+    fn csv_runs_can_stop_early() {
+        let runs = read_text(
+            "observed-csv",
+            "csv",
+            "player_count,ts_0,ts_1,it_0_0,it_0_1,it_1_0\n\
+             3,ruby,,it_flame_bow,it_lion_charm,\n\
+             1,normal,opal,,it_old_bonnet,it_opal_necklace\n",
+        )
+        .unwrap();
+        assert_eq!(
+            runs,
+            vec![
+                ObservedRun {
+                    player_count: PlayerCount::new(3).unwrap(),
+                    spheres: vec![(
+                        Treasuresphere::Ruby,
+                        vec![item("it_flame_bow"), item("it_lion_charm")]
+                    )],
+                },
+                ObservedRun {
+                    player_count: PlayerCount::new(1).unwrap(),
+                    spheres: vec![
+                        (Treasuresphere::Normal, vec![item("it_old_bonnet")]),
+                        (Treasuresphere::Opal, vec![item("it_opal_necklace")]),
+                    ],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn csv_errors_name_the_row() {
+        let error = read_text(
+            "observed-bad-csv",
+            "csv",
+            "player_count,ts_0,it_0_0\n2,ruby,it_flame_bow\n2,amber,it_flame_bow\n",
+        )
+        .unwrap_err();
+        assert!(format!("{:#}", error).contains(": row 2: Unknown treasuresphere: amber"));
+
+        assert!(read_text("observed-players", "csv", "player_count,ts_0\n5,ruby\n").is_err());
+    }
+
+    #[test]
+    fn json_round_trips_run_json() {
+        let ts = vec![Treasuresphere::Emerald, Treasuresphere::Normal];
+        let items = vec![
+            item("it_flame_bow"),
+            item("it_lion_charm"),
+            item("it_old_bonnet"),
+        ];
+        let player_count = PlayerCount::new(4).unwrap();
+        let json = json!([run_json(
+            &(ts.clone(), items.clone()),
+            &player_count,
+            &[2, 1]
+        )]);
+
+        let runs = read_text("observed-json", "json", &json.to_string()).unwrap();
+        assert_eq!(
+            runs,
+            vec![ObservedRun {
+                player_count,
+                spheres: vec![(ts[0], items[..2].to_vec()), (ts[1], items[2..].to_vec())],
+            }]
+        );
+    }
+
+    #[test]
+    fn json_takes_item_indices_and_names_the_run() {
+        let index = item("it_flame_bow").index();
+        let text = format!(
+            r#"[{{"player_count": 2, "spheres": [{{"color": "ruby", "items": [{}]}}]}}]"#,
+            index
+        );
+        let runs = read_text("observed-index", "json", &text).unwrap();
+        assert_eq!(runs[0].spheres[0].1, vec![item("it_flame_bow")]);
+
+        let error = read_text(
+            "observed-bad-json",
+            "json",
+            r#"[{"player_count": 2}, {"spheres": []}]"#,
+        )
+        .unwrap_err();
+        assert!(format!("{:#}", error).ends_with(": run 1: Missing player_count"));
+        assert!(read_text("observed-object", "json", "{}").is_err());
+    }
+}
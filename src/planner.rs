@@ -0,0 +1,230 @@
+// Module for the build planner, the odds of completing a target loadout
+use crate::ids::{ItemId, SpherePos};
+use crate::loot;
+use crate::unlocks::parse_item;
+use crate::{simulate_runs, PoolArgs, RunSettings, SimArgs};
+use anyhow::{bail, Context, Error, Result};
+use csv::Writer;
+use loot::{sets, TS_COUNT};
+use std::io;
+use std::str::FromStr;
+
+#[derive(clap::Args, Debug)]
+pub struct PlanArgs {
+    /// Requirement of the loadout, repeat for more: an item, `set:NAME`, a list of
+    /// both joined by ',' (all needed) or `K of LIST` (any K needed)
+    #[arg(long, required = true)]
+    need: Vec<Requirement>,
+
+    #[command(flatten)]
+    sim: SimArgs,
+
+    #[command(flatten)]
+    pool: PoolArgs,
+}
+
+/// Any `need` of `items` must be found
+#[derive(Debug, Clone)]
+pub struct Requirement {
+    need: usize,
+    items: Vec<ItemId>,
+}
+
+impl FromStr for Requirement {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let (need, list) = match s.split_once(" of ") {
+            Some((k, list)) => (
+                Some(
+                    k.trim()
+                        .parse::<usize>()
+                        .with_context(|| format!("Invalid count '{}'", k))?,
+                ),
+                list,
+            ),
+            None => (None, s),
+        };
+
+        let mut items: Vec<ItemId> = Vec::new();
+        for entry in list.split(',').map(str::trim) {
+            match entry.strip_prefix("set:") {
+                Some(name) => match sets::set_index(name) {
+                    Some(set) => items.extend(sets::items_in_set(&set)),
+                    None => bail!("Unknown set: {}", name),
+                },
+                None => items.push(parse_item(entry)?),
+            }
+        }
+        items.sort_unstable();
+        items.dedup();
+
+        let need = need.unwrap_or(items.len());
+        if need == 0 || need > items.len() {
+            bail!("Can't need {} of {} items", need, items.len());
+        }
+        Ok(Requirement { need, items })
+    }
+}
+
+/// Treasuresphere after which every requirement is met, if ever
+///
+/// `items` is a run as returned by `generate_it`, `loot_counts` splits it per treasuresphere.
+pub fn completed_at(
+    requirements: &[Requirement],
+    items: &[ItemId],
+    loot_counts: &[usize],
+) -> Option<SpherePos> {
+    let mut found: Vec<usize> = vec![0; requirements.len()];
+    let mut start = 0;
+
+    for (t, loot_count) in SpherePos::all().zip(loot_counts) {
+        for item in &items[start..start + loot_count] {
+            for (r, requirement) in requirements.iter().enumerate() {
+                if requirement.items.contains(item) {
+                    found[r] += 1;
+                }
+            }
+        }
+        start += loot_count;
+
+        if requirements.iter().zip(&found).all(|(r, f)| *f >= r.need) {
+            return Some(t);
+        }
+    }
+
+    None
+}
+
+/// Simulates runs for every player count and writes, as csv to stdout,
+/// the probability of having the loadout by each treasuresphere
+pub fn run(args: &PlanArgs) -> Result<(), Error> {
+    let pools = args.pool.load()?;
+    let game_count = args.sim.run_count as usize;
+
+    let mut wtr = Writer::from_writer(io::stdout());
+    wtr.write_field("player_count")?;
+    for t in 0..*TS_COUNT {
+        wtr.write_field(format!("by_ts_{}", t))?;
+    }
+    wtr.write_record(None::<&[u8]>)?;
+
+    for player_count in args.sim.player_count.iter().copied() {
+        let loot_counts = loot::player_loot::loot_counts(player_count);
+        let settings = RunSettings::new(args.sim.seed, player_count, &pools);
+
+        // Runs completed at each treasuresphere
+        let completed: Vec<u64> = simulate_runs(
+            &game_count,
+            &settings,
+            || vec![0u64; *TS_COUNT],
+            |mut acc, _, it| {
+                if let Some(t) = completed_at(&args.need, it, &loot_counts) {
+                    acc[t.index()] += 1;
+                }
+                acc
+            },
+            |a, b| a.iter().zip(&b).map(|(x, y)| x + y).collect(),
+        )?;
+
+        wtr.write_field(player_count.to_string())?;
+        let mut cumulative = 0;
+        for count in completed {
+            cumulative += count;
+            wtr.write_field(format!("{:.6}", cumulative as f64 / game_count as f64))?;
+        }
+        wtr.write_record(None::<&[u8]>)?;
+    }
+
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ids::PlayerCount;
+
+    fn requirement(s: &str) -> Requirement {
+        s.parse().unwrap()
+    }
+
+    fn item(name: &str) -> ItemId {
+        parse_item(name).unwrap()
+    }
+
+    #[test]
+    fn requirements_parse_items_sets_and_counts() {
+        let one = requirement("it_opal_necklace");
+        assert_eq!((one.need, one.items), (1, vec![item("it_opal_necklace")]));
+
+        let night: Vec<ItemId> = sets::items_in_set(&sets::set_index("night").unwrap()).collect();
+        let all = requirement("set:night");
+        assert_eq!((all.need, all.items), (8, night.clone()));
+
+        // A night item given twice is only counted once
+        let any = requirement(&format!(" 2 of {}, set:night", night[0]));
+        assert_eq!((any.need, any.items), (2, night));
+    }
+
+    #[test]
+    fn requirements_reject_bad_input() {
+        let error = |s: &str| format!("{:#}", s.parse::<Requirement>().unwrap_err());
+        assert_eq!(error("set:nosuchset"), "Unknown set: nosuchset");
+        assert!(error("it_no_such_item").contains("Unknown item"));
+        assert!(error("k of it_opal_necklace").starts_with("Invalid count 'k'"));
+        assert_eq!(error("0 of set:night"), "Can't need 0 of 8 items");
+        assert_eq!(
+            error("3 of it_opal_necklace,it_topaz_charm"),
+            "Can't need 3 of 2 items"
+        );
+    }
+
+    #[test]
+    fn completed_at_is_the_sphere_meeting_the_last_requirement() {
+        let loot_counts = loot::player_loot::loot_counts(PlayerCount::new(1).unwrap());
+        let mut items: Vec<ItemId> = (100..122).map(|x| ItemId::new(x).unwrap()).collect();
+        let opal = item("it_opal_necklace");
+        let topaz = item("it_topaz_charm");
+        items[6] = opal; // ts_1
+        items[11] = topaz; // ts_2, after the 5 + 5 items of ts_0 and ts_1
+
+        let need = |s: &str| vec![requirement(s)];
+        let at = |t: usize| Some(SpherePos::new(t).unwrap());
+        assert_eq!(
+            completed_at(&need("it_opal_necklace"), &items, &loot_counts),
+            at(1)
+        );
+        assert_eq!(
+            completed_at(
+                &need("it_opal_necklace,it_topaz_charm"),
+                &items,
+                &loot_counts
+            ),
+            at(2)
+        );
+        assert_eq!(
+            completed_at(
+                &need("1 of it_topaz_charm,it_opal_necklace"),
+                &items,
+                &loot_counts
+            ),
+            at(1)
+        );
+        let both = [
+            requirement("it_topaz_charm"),
+            requirement("it_opal_necklace"),
+        ];
+        assert_eq!(completed_at(&both, &items, &loot_counts), at(2));
+
+        items[11] = ItemId::new(99).unwrap();
+        assert_eq!(
+            completed_at(
+                &need("it_opal_necklace,it_topaz_charm"),
+                &items,
+                &loot_counts
+            ),
+            None
+        );
+    }
+}
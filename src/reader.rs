@@ -0,0 +1,54 @@
+// Module for reading back csv files in the writer.rs layout
+use crate::compress;
+use crate::loot;
+use anyhow::{bail, Error, Result};
+use csv::{Reader, ReaderBuilder, StringRecord};
+use loot::{IT_FOUND_MAX_PER_TS, TS_COUNT};
+use std::io::Read;
+
+/// Column indices of the fields in a file, found from its headers
+#[derive(Debug, Clone)]
+pub struct Layout {
+    pub player_count: usize,
+    pub ts: Vec<Option<usize>>,      // [position]
+    pub it: Vec<Vec<Option<usize>>>, // [position][index]
+}
+
+impl Layout {
+    pub fn from_headers(headers: &StringRecord) -> Result<Self, Error> {
+        let column = |name: &str| headers.iter().position(|x| x == name);
+        let Some(player_count) = column("player_count") else {
+            bail!("Missing player_count column, is this a rns-loot-sim csv file?");
+        };
+        Ok(Layout {
+            player_count,
+            ts: (0..*TS_COUNT)
+                .map(|t| column(&format!("ts_{}", t)))
+                .collect(),
+            it: (0..*TS_COUNT)
+                .map(|t| {
+                    (0..*IT_FOUND_MAX_PER_TS)
+                        .map(|i| column(&format!("it_{}_{}", t, i)))
+                        .collect()
+                })
+                .collect(),
+        })
+    }
+
+    /// Cell of a column, empty if the file doesn't have the column
+    pub fn cell<'a>(&self, record: &'a StringRecord, column: &Option<usize>) -> &'a str {
+        column.and_then(|c| record.get(c)).unwrap_or("").trim()
+    }
+}
+
+/// Opens a csv file for reading, compressed or not
+pub fn open(path: &str) -> Result<Reader<Box<dyn Read>>, Error> {
+    Ok(Reader::from_reader(compress::open(path)?))
+}
+
+/// Like [`open`], but rows may have more or fewer fields than the headers
+pub fn open_flexible(path: &str) -> Result<Reader<Box<dyn Read>>, Error> {
+    Ok(ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(compress::open(path)?))
+}
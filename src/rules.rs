@@ -0,0 +1,305 @@
+// Module for challenge rules layered on top of the item pools
+use crate::ids::{ItemId, SpherePos};
+use crate::loot;
+use crate::unlocks::{parse_item, Unlocks};
+use anyhow::{bail, Context, Error, Result};
+use loot::sets;
+use loot::treasuresphere::{is_item_in_ts_pos, Colors as Treasuresphere, COLORS};
+use std::fs;
+
+/// Bans, forced inclusions, set-only restrictions and position limits
+///
+/// Rules files are read line by line, blank lines and anything after `#` are ignored:
+/// ```text
+/// ban it_opal_necklace     # item never drops
+/// ban-set night            # no item of the set drops
+/// force it_silver_coin     # item stays in its pools even if locked, banned or outside only-sets
+/// only-sets arcane wind    # only items of these sets drop
+/// limit it_vega_spear 2    # item can't drop in the last 2 treasurespheres
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Rules {
+    pub banned: Vec<ItemId>,
+    pub forced: Vec<ItemId>,
+    pub only_sets: Vec<usize>,        // set indices
+    pub limits: Vec<(ItemId, usize)>, // (item, last n treasurespheres)
+}
+
+impl Rules {
+    /// Reads a rules file, see [`Rules`] for the format
+    pub fn from_file(path: &str) -> Result<Self, Error> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Could not read rules file '{}'", path))?;
+        Rules::parse(&text).with_context(|| format!("Invalid rules file '{}'", path))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut rules = Rules::default();
+
+        for (line_no, line) in text.lines().enumerate() {
+            let entry = line.split('#').next().unwrap_or("").trim();
+            let mut words = entry.split_whitespace();
+            let Some(keyword) = words.next() else {
+                continue;
+            };
+            let args: Vec<&str> = words.collect();
+
+            let parsed: Result<(), Error> = (|| {
+                if args.is_empty() {
+                    bail!("{} expects at least one argument", keyword);
+                }
+                match keyword {
+                    "ban" => {
+                        for arg in &args {
+                            rules.banned.push(parse_item(arg)?);
+                        }
+                    }
+                    "ban-set" => {
+                        for arg in &args {
+                            rules.banned.extend(sets::items_in_set(&parse_set(arg)?));
+                        }
+                    }
+                    "force" => {
+                        for arg in &args {
+                            rules.forced.push(parse_item(arg)?);
+                        }
+                    }
+                    "only-sets" => {
+                        for arg in &args {
+                            rules.only_sets.push(parse_set(arg)?);
+                        }
+                    }
+                    "limit" => {
+                        let [item, last] = args[..] else {
+                            bail!("limit expects an item and a treasuresphere count");
+                        };
+                        rules.limits.push((parse_item(item)?, last.parse()?));
+                    }
+                    _ => bail!("Unknown rule: {}", keyword),
+                }
+                Ok(())
+            })();
+            parsed.with_context(|| format!("line {}", line_no + 1))?;
+        }
+
+        Ok(rules)
+    }
+
+    fn is_allowed(&self, item: &ItemId, unlocks: &Unlocks) -> bool {
+        if self.forced.contains(item) {
+            return true;
+        }
+        unlocks.is_unlocked(item)
+            && !self.banned.contains(item)
+            && (self.only_sets.is_empty() || self.only_sets.contains(&sets::set_of(item)))
+    }
+
+    /// Checks if the item is valid in the current Treasuresphere position,
+    /// with the rule limits added on top of `NOT_IN_LAST_SPHERES`
+    pub fn is_item_in_ts_pos(&self, item: &ItemId, pos: &SpherePos) -> bool {
+        let delta = pos.spheres_left(); // 1..=6
+        let limited = self
+            .limits
+            .iter()
+            .any(|(it, last)| it == item && *last >= delta);
+        !limited && is_item_in_ts_pos(item, pos)
+    }
+
+    /// Rules written back in the file format, used for the output metadata
+    pub fn describe(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        lines.extend(self.banned.iter().map(|x| format!("ban {}", x)));
+        lines.extend(self.forced.iter().map(|x| format!("force {}", x)));
+        if !self.only_sets.is_empty() {
+            let names: Vec<&str> = self.only_sets.iter().map(|x| sets::SET_NAMES[*x]).collect();
+            lines.push(format!("only-sets {}", names.join(" ")));
+        }
+        lines.extend(
+            self.limits
+                .iter()
+                .map(|(x, last)| format!("limit {} {}", x, last)),
+        );
+        lines
+    }
+}
+
+/// Items each treasuresphere can roll under an unlock profile and rules
+///
+/// Worked out once per invocation, rolling a treasuresphere then only copies its pool.
+#[derive(Debug, Clone)]
+pub struct ItemPools {
+    rules: Rules,
+    pools: Vec<Vec<ItemId>>, // [color in COLORS order], in pool order
+}
+
+impl ItemPools {
+    pub fn new(unlocks: &Unlocks, rules: Rules) -> Self {
+        let pools = COLORS
+            .iter()
+            .map(|ts| {
+                ts.pool()
+                    .iter()
+                    .filter(|x| rules.is_allowed(x, unlocks))
+                    .copied()
+                    .collect()
+            })
+            .collect();
+        ItemPools { rules, pools }
+    }
+
+    pub fn rules(&self) -> &Rules {
+        &self.rules
+    }
+
+    /// Items the treasuresphere can roll, keeping the pool order
+    pub fn get(&self, ts: &Treasuresphere) -> &[ItemId] {
+        &self.pools[*ts as usize]
+    }
+}
+
+fn parse_set(name: &str) -> Result<usize, Error> {
+    match sets::set_index(name) {
+        Some(set) => Ok(set),
+        None => bail!(
+            "Unknown set: {} (sets: {})",
+            name,
+            sets::SET_NAMES.join(", ")
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ids::PlayerCount;
+    use crate::{generate_it, generate_ts, run_rng};
+
+    fn item(name: &str) -> ItemId {
+        parse_item(name).unwrap()
+    }
+
+    fn pos(t: usize) -> SpherePos {
+        SpherePos::new(t).unwrap()
+    }
+
+    fn error(text: &str) -> String {
+        format!("{:#}", Rules::parse(text).unwrap_err())
+    }
+
+    #[test]
+    fn parse_reads_every_directive() {
+        let rules = Rules::parse(
+            "# event rules\n\
+             ban it_opal_necklace 3   # by name and index\n\
+             \n\
+             ban-set night\n\
+             force it_silver_coin\n\
+             only-sets arcane wind\n\
+             limit it_vega_spear 2\n",
+        )
+        .unwrap();
+
+        let night = sets::set_index("night").unwrap();
+        let mut banned = vec![item("it_opal_necklace"), ItemId::new(3).unwrap()];
+        banned.extend(sets::items_in_set(&night));
+        assert_eq!(rules.banned, banned);
+        assert_eq!(rules.forced, vec![item("it_silver_coin")]);
+        assert_eq!(
+            rules.only_sets,
+            vec![
+                sets::set_index("arcane").unwrap(),
+                sets::set_index("wind").unwrap()
+            ]
+        );
+        assert_eq!(rules.limits, vec![(item("it_vega_spear"), 2)]);
+    }
+
+    #[test]
+    fn parse_rejects_malformed_lines() {
+        assert_eq!(
+            error("ban it_opal_necklace\nfreeze it_opal_necklace"),
+            "line 2: Unknown rule: freeze"
+        );
+        assert_eq!(error("ban"), "line 1: ban expects at least one argument");
+        assert_eq!(
+            error("limit it_vega_spear"),
+            "line 1: limit expects an item and a treasuresphere count"
+        );
+        assert!(error("limit it_vega_spear 2 3").starts_with("line 1: limit expects"));
+        assert!(error("limit it_vega_spear two").starts_with("line 1: "));
+        assert!(error("force it_no_such_item").contains("Unknown item"));
+        assert!(error("ban-set nosuchset").contains("Unknown set: nosuchset"));
+        assert!(error("only-sets arcane nosuchset").contains("Unknown set"));
+    }
+
+    #[test]
+    fn forced_items_beat_locks_bans_and_only_sets() {
+        let rules = Rules::parse(
+            "ban it_opal_necklace it_silver_coin\nforce it_silver_coin\nonly-sets arcane\n",
+        )
+        .unwrap();
+        let unlocks = Unlocks::all();
+        assert!(!rules.is_allowed(&item("it_opal_necklace"), &unlocks));
+        assert!(rules.is_allowed(&item("it_silver_coin"), &unlocks));
+
+        let arcane = sets::items_in_set(&sets::set_index("arcane").unwrap())
+            .next()
+            .unwrap();
+        let night = sets::items_in_set(&sets::set_index("night").unwrap())
+            .next()
+            .unwrap();
+        assert!(rules.is_allowed(&arcane, &unlocks));
+        assert!(!rules.is_allowed(&night, &unlocks));
+        assert!(Rules::default().is_allowed(&night, &unlocks));
+    }
+
+    #[test]
+    fn limits_add_to_the_last_spheres_rule() {
+        let vega = item("it_vega_spear");
+        let rules = Rules::parse("limit it_vega_spear 2").unwrap();
+        assert!(rules.is_item_in_ts_pos(&vega, &pos(3)));
+        assert!(!rules.is_item_in_ts_pos(&vega, &pos(4)));
+        assert!(!rules.is_item_in_ts_pos(&vega, &pos(5)));
+
+        // it_topaz_charm can't drop in the last 2 treasurespheres without any rules
+        let topaz = item("it_topaz_charm");
+        assert!(Rules::default().is_item_in_ts_pos(&topaz, &pos(3)));
+        assert!(!Rules::default().is_item_in_ts_pos(&topaz, &pos(4)));
+    }
+
+    #[test]
+    fn describe_writes_rules_back_in_the_file_format() {
+        let text = "ban it_opal_necklace\nforce it_silver_coin\nonly-sets arcane wind\nlimit it_vega_spear 2";
+        let rules = Rules::parse(text).unwrap();
+        assert_eq!(rules.describe().join("\n"), text);
+        assert!(Rules::default().describe().is_empty());
+    }
+
+    #[test]
+    fn forced_items_drop_and_banned_items_never_do() {
+        // The silver coin's whole set is banned, the coin itself forced back in
+        let coin = item("it_silver_coin");
+        let set = sets::items_in_set(&sets::set_of(&coin));
+        let rules = Rules::parse(&format!(
+            "ban-set {}\nban it_opal_necklace\nforce it_silver_coin",
+            sets::SET_NAMES[sets::set_of(&coin)]
+        ))
+        .unwrap();
+        let pools = ItemPools::new(&Unlocks::all(), rules);
+        let banned: Vec<ItemId> = set
+            .filter(|x| *x != coin)
+            .chain([item("it_opal_necklace")])
+            .collect();
+
+        let player_count = PlayerCount::new(4).unwrap();
+        let mut coin_runs = 0;
+        for i in 0..500 {
+            let mut rng = run_rng(&Some(3), &i);
+            let ts = generate_ts(&mut rng);
+            let items = generate_it(&ts, &mut rng, &player_count, &pools).unwrap();
+            assert!(items.iter().all(|x| !banned.contains(x)), "{:?}", items);
+            coin_runs += items.contains(&coin) as usize;
+        }
+        assert!(coin_runs > 0);
+    }
+}
@@ -0,0 +1,346 @@
+// Module for the local HTTP server answering simulations with json
+//
+// Every endpoint is a GET taking its options in the query string:
+// /simulate    runs, player_count, seed: the runs themselves
+// /stats       runs, player_count, seed: item probabilities
+// /probability filter, runs, player_count, seed: odds of a run matching the filter
+// /replay      seed, run, player_count: one run, as row `run` of a file with that seed
+use crate::filter::{Filter, RunView};
+use crate::ids::{ItemId, PlayerCount};
+use crate::loot;
+use crate::observed::run_json;
+use crate::rules::ItemPools;
+use crate::stats;
+use crate::{generate_run, simulate_runs, PoolArgs, RunSettings};
+use anyhow::{anyhow, bail, Error, Result};
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+#[derive(clap::Args, Debug)]
+pub struct ServeArgs {
+    /// Port to listen on, only on localhost
+    #[arg(long, default_value_t = 8080)]
+    port: u16,
+
+    /// Most runs a single request can simulate
+    #[arg(long, default_value_t = 20000, value_parser(clap::value_parser!(u64).range(1..=200000)))]
+    max_runs: u64,
+
+    /// Longest request url accepted, in bytes
+    #[arg(long, default_value_t = 2048)]
+    max_url: usize,
+
+    /// Stop after answering this many requests, never if not used
+    #[arg(long)]
+    max_requests: Option<u64>,
+
+    /// Unlocks and rules used by every request
+    #[command(flatten)]
+    pool: PoolArgs,
+}
+
+/// Settings shared by every request
+struct Settings {
+    max_runs: usize,
+    pools: ItemPools,
+}
+
+/// Options of a request, from its query string
+struct Query(Vec<(String, String)>);
+
+impl Query {
+    fn parse(url: &str) -> Result<(String, Query), Error> {
+        let (path, query) = url.split_once('?').unwrap_or((url, ""));
+        let pairs = query
+            .split('&')
+            .filter(|x| !x.is_empty())
+            .map(|x| {
+                let (key, value) = x.split_once('=').unwrap_or((x, ""));
+                Ok((percent_decode(key)?, percent_decode(value)?))
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok((path.to_string(), Query(pairs)))
+    }
+
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn number<T: std::str::FromStr>(&self, key: &str) -> Result<Option<T>, Error> {
+        self.get(key)
+            .map(|x| x.parse().map_err(|_| anyhow!("Invalid {}: {}", key, x)))
+            .transpose()
+    }
+}
+
+/// Decodes `%XX` escapes, exactly two hex digits, and `+` as a space
+fn percent_decode(s: &str) -> Result<String, Error> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.bytes();
+    while let Some(b) = rest.next() {
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex: Vec<u8> = rest.by_ref().take(2).collect();
+                let digits = match hex[..] {
+                    [high, low] => (high as char).to_digit(16).zip((low as char).to_digit(16)),
+                    _ => None,
+                };
+                let Some((high, low)) = digits else {
+                    bail!("Invalid escape %{}", String::from_utf8_lossy(&hex));
+                };
+                bytes.push((high * 16 + low) as u8);
+            }
+            b => bytes.push(b),
+        }
+    }
+    Ok(String::from_utf8(bytes)?)
+}
+
+/// Serves requests on localhost until stopped, or `--max-requests` are answered
+pub fn run(args: &ServeArgs) -> Result<(), Error> {
+    let settings = Settings {
+        max_runs: args.max_runs as usize,
+        pools: args.pool.load()?,
+    };
+    let server = Server::http(("127.0.0.1", args.port)).map_err(|e| anyhow!(e))?;
+    eprintln!("Listening on http://127.0.0.1:{}", args.port);
+
+    for (answered, request) in server.incoming_requests().enumerate() {
+        let (status, body) = if request.url().len() > args.max_url {
+            (414, json!({ "error": "Request url too long" }))
+        } else if *request.method() != Method::Get {
+            (405, json!({ "error": "Only GET requests are served" }))
+        } else {
+            match respond(&settings, request.url()) {
+                Ok(Some(body)) => (200, body),
+                Ok(None) => (404, json!({ "error": "Unknown endpoint" })),
+                Err(e) => (400, json!({ "error": e.to_string() })),
+            }
+        };
+        // A client leaving early shouldn't stop the server
+        if let Err(e) = reply(request, status, &body) {
+            eprintln!("Could not reply: {}", e);
+        }
+
+        if args.max_requests.is_some_and(|x| answered as u64 + 1 >= x) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn reply(request: Request, status: u16, body: &Value) -> Result<(), Error> {
+    let header = Header::from_bytes("Content-Type", "application/json")
+        .map_err(|_| anyhow!("Invalid header"))?;
+    let response = Response::from_string(body.to_string())
+        .with_status_code(status)
+        .with_header(header);
+    request.respond(response)?;
+    Ok(())
+}
+
+/// Answers an endpoint, none if there's no such endpoint
+fn respond(settings: &Settings, url: &str) -> Result<Option<Value>, Error> {
+    let (path, query) = Query::parse(url)?;
+    let player_count = PlayerCount::new(query.number::<usize>("player_count")?.unwrap_or(1))?;
+    let seed = query.number::<u64>("seed")?;
+    let runs = query
+        .number::<usize>("runs")?
+        .unwrap_or(settings.max_runs.min(1000));
+    if runs == 0 || runs > settings.max_runs {
+        bail!("runs must be from 1 to {}", settings.max_runs);
+    }
+    let loot_counts = loot::player_loot::loot_counts(player_count);
+    let run_settings = RunSettings::new(seed, player_count, &settings.pools);
+
+    let body = match path.as_str() {
+        "/simulate" => {
+            let runs: Vec<Value> = simulate_runs(
+                &runs,
+                &run_settings,
+                Vec::new,
+                |mut acc, ts, it| {
+                    let run = (ts.to_vec(), it.to_vec());
+                    acc.push(run_json(&run, &player_count, &loot_counts));
+                    acc
+                },
+                |mut a, b| {
+                    a.extend(b);
+                    a
+                },
+            )?;
+            json!({ "runs": runs })
+        }
+        "/stats" => {
+            let stats = stats::simulate(&runs, &run_settings)?;
+            let items: Vec<Value> = ItemId::all()
+                .map(|i| {
+                    json!({
+                        "item": i.name(),
+                        "probability": stats.item_probability(&i),
+                        "mean_position": stats.item_mean_position(&i),
+                    })
+                })
+                .collect();
+            json!({ "player_count": player_count.get(), "runs": stats.runs, "items": items })
+        }
+        "/probability" => {
+            let Some(source) = query.get("filter") else {
+                bail!("Missing filter");
+            };
+            let filter = Filter::parse(source)?;
+            let matched = simulate_runs(
+                &runs,
+                &run_settings,
+                || 0,
+                |acc, ts, items| {
+                    let run = RunView {
+                        ts,
+                        items,
+                        loot_counts: &loot_counts,
+                    };
+                    acc + filter.matches(&run) as usize
+                },
+                |a, b| a + b,
+            )?;
+            json!({
+                "filter": filter.source(),
+                "player_count": player_count.get(),
+                "runs": runs,
+                "matched": matched,
+                "probability": matched as f64 / runs as f64,
+            })
+        }
+        "/replay" => {
+            if seed.is_none() {
+                bail!("Missing seed");
+            }
+            let run = query.number::<usize>("run")?.unwrap_or(0);
+            run_json(
+                &generate_run(run, &run_settings)?,
+                &player_count,
+                &loot_counts,
+            )
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::Rules;
+    use crate::unlocks::Unlocks;
+
+    const GOLDEN: &str = include_str!("../tests/golden/v1/s5_p4_n20.csv");
+
+    fn settings() -> Settings {
+        Settings {
+            max_runs: 100,
+            pools: ItemPools::new(&Unlocks::all(), Rules::default()),
+        }
+    }
+
+    fn body(url: &str) -> Value {
+        respond(&settings(), url).unwrap().unwrap()
+    }
+
+    fn error(url: &str) -> String {
+        respond(&settings(), url).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn queries_are_split_and_decoded() {
+        let (path, query) =
+            Query::parse("/probability?filter=has%28it_flame_bow%29+%26&seed=3&x").unwrap();
+        assert_eq!(path, "/probability");
+        assert_eq!(query.get("filter"), Some("has(it_flame_bow) &"));
+        assert_eq!(query.number::<u64>("seed").unwrap(), Some(3));
+        assert_eq!(query.get("x"), Some(""));
+        assert_eq!(query.get("runs"), None);
+        assert_eq!(
+            query.number::<u64>("filter").unwrap_err().to_string(),
+            "Invalid filter: has(it_flame_bow) &"
+        );
+
+        let (path, query) = Query::parse("/stats").unwrap();
+        assert_eq!(path, "/stats");
+        assert_eq!(query.get("seed"), None);
+    }
+
+    #[test]
+    fn escapes_need_two_hex_digits() {
+        assert_eq!(percent_decode("a%2Cb%2c").unwrap(), "a,b,");
+        assert_eq!(percent_decode("%C3%A9").unwrap(), "é");
+        assert_eq!(
+            percent_decode("%4").unwrap_err().to_string(),
+            "Invalid escape %4"
+        );
+        assert_eq!(
+            percent_decode("%").unwrap_err().to_string(),
+            "Invalid escape %"
+        );
+        assert_eq!(
+            percent_decode("%4g").unwrap_err().to_string(),
+            "Invalid escape %4g"
+        );
+        assert!(percent_decode("%+1").is_err());
+        assert!(percent_decode("%FF").is_err()); // not utf-8
+    }
+
+    #[test]
+    fn bad_requests_are_errors() {
+        assert_eq!(error("/stats?runs=0"), "runs must be from 1 to 100");
+        assert_eq!(error("/stats?runs=101"), "runs must be from 1 to 100");
+        assert_eq!(error("/stats?runs=ten"), "Invalid runs: ten");
+        assert!(error("/stats?player_count=5").starts_with("Invalid player count: 5"));
+        assert_eq!(error("/probability?runs=10"), "Missing filter");
+        assert_eq!(error("/replay?run=2"), "Missing seed");
+        assert!(respond(&settings(), "/nothing").unwrap().is_none());
+    }
+
+    #[test]
+    fn endpoints_answer_with_the_runs_asked() {
+        let runs = body("/simulate?runs=7&seed=2&player_count=3");
+        assert_eq!(runs["runs"].as_array().unwrap().len(), 7);
+        assert_eq!(runs["runs"][0]["player_count"], 3);
+
+        let stats = body("/stats?runs=20&seed=2");
+        assert_eq!(stats["runs"], 20);
+        assert_eq!(
+            stats["items"].as_array().unwrap().len(),
+            ItemId::all().count()
+        );
+
+        let always = body("/probability?runs=20&seed=2&filter=items%3E0");
+        assert_eq!(always["matched"], 20);
+        assert_eq!(always["probability"], 1.0);
+    }
+
+    #[test]
+    fn replay_matches_the_generator_row() {
+        let mut rdr = csv::Reader::from_reader(GOLDEN.as_bytes());
+        let row = rdr.records().nth(3).unwrap().unwrap();
+        let replay = body("/replay?seed=5&run=3&player_count=4");
+        let mut cells: Vec<String> = vec![replay["player_count"].to_string()];
+        for sphere in replay["spheres"].as_array().unwrap() {
+            cells.push(sphere["color"].as_str().unwrap().to_string());
+        }
+        for sphere in replay["spheres"].as_array().unwrap() {
+            for item in sphere["items"].as_array().unwrap() {
+                cells.push(item.as_str().unwrap().to_string());
+            }
+        }
+        let golden: Vec<&str> = row.iter().filter(|x| !x.is_empty()).collect();
+        assert_eq!(cells, golden);
+
+        // Row 3 of /simulate is the same run
+        let runs = body("/simulate?runs=4&seed=5&player_count=4");
+        assert_eq!(runs["runs"][3], replay);
+    }
+}
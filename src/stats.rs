@@ -0,0 +1,394 @@
+// Module for item and treasuresphere statistics over many runs
+use crate::ids::{ItemId, SpherePos};
+use crate::loot;
+use crate::observed::ObservedRun;
+use crate::{simulate_runs, RunSettings};
+use anyhow::{Error, Result};
+use loot::treasuresphere::{Colors as Treasuresphere, COLORS};
+use loot::{IT_COUNT, TS_COUNT};
+
+/// Counts of what was found over a number of runs
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunStats {
+    pub runs: u64,
+    pub ts_counts: Vec<Vec<u64>>, // [position][color in COLORS order]
+    pub it_counts: Vec<Vec<u64>>, // [item][position]
+    pub it_color_counts: Vec<Vec<Vec<u64>>>, // [position][color][item], by the sphere's color
+}
+
+impl Default for RunStats {
+    fn default() -> Self {
+        RunStats {
+            runs: 0,
+            ts_counts: vec![vec![0; COLORS.len()]; *TS_COUNT],
+            it_counts: vec![vec![0; *TS_COUNT]; *IT_COUNT],
+            it_color_counts: vec![vec![vec![0; *IT_COUNT]; COLORS.len()]; *TS_COUNT],
+        }
+    }
+}
+
+impl RunStats {
+    /// Adds a run, `loot_counts` splits the items per treasuresphere
+    pub fn add(&mut self, ts: &[Treasuresphere], items: &[ItemId], loot_counts: &[usize]) {
+        self.runs += 1;
+        let mut start = 0;
+        for (t, loot_count) in loot_counts.iter().enumerate() {
+            let color = COLORS.iter().position(|x| Some(x) == ts.get(t));
+            if let Some(c) = color {
+                self.ts_counts[t][c] += 1;
+            }
+            for item in &items[start..start + loot_count] {
+                self.it_counts[item.index()][t] += 1;
+                if let Some(c) = color {
+                    self.it_color_counts[t][c][item.index()] += 1;
+                }
+            }
+            start += loot_count;
+        }
+    }
+
+    /// Adds a run seen in the game, which can stop before the last treasuresphere
+    pub fn add_observed(&mut self, run: &ObservedRun) {
+        self.runs += 1;
+        for (t, (ts, items)) in run.spheres.iter().enumerate().take(*TS_COUNT) {
+            let color = COLORS.iter().position(|x| x == ts);
+            if let Some(c) = color {
+                self.ts_counts[t][c] += 1;
+            }
+            for item in items {
+                self.it_counts[item.index()][t] += 1;
+                if let Some(c) = color {
+                    self.it_color_counts[t][c][item.index()] += 1;
+                }
+            }
+        }
+    }
+
+    pub fn merge(mut self, other: RunStats) -> RunStats {
+        self.runs += other.runs;
+        for (x, y) in self
+            .ts_counts
+            .iter_mut()
+            .flatten()
+            .zip(other.ts_counts.iter().flatten())
+        {
+            *x += y;
+        }
+        for (x, y) in self
+            .it_counts
+            .iter_mut()
+            .flatten()
+            .zip(other.it_counts.iter().flatten())
+        {
+            *x += y;
+        }
+        for (x, y) in self
+            .it_color_counts
+            .iter_mut()
+            .flatten()
+            .flatten()
+            .zip(other.it_color_counts.iter().flatten().flatten())
+        {
+            *x += y;
+        }
+        self
+    }
+
+    /// Runs the item was found in
+    pub fn item_found(&self, item: &ItemId) -> u64 {
+        self.it_counts[item.index()].iter().sum()
+    }
+
+    /// Probability of a run finding the item
+    pub fn item_probability(&self, item: &ItemId) -> f64 {
+        self.ratio(self.item_found(item))
+    }
+
+    /// Probability of a run finding the item in the t-th treasuresphere
+    pub fn item_probability_at(&self, item: &ItemId, t: &SpherePos) -> f64 {
+        self.ratio(self.it_counts[item.index()][t.index()])
+    }
+
+    /// Mean treasuresphere position the item is found in, if ever found
+    pub fn item_mean_position(&self, item: &ItemId) -> Option<f64> {
+        let found = self.item_found(item);
+        let position_sum: u64 = self.it_counts[item.index()]
+            .iter()
+            .enumerate()
+            .map(|(t, n)| t as u64 * n)
+            .sum();
+        (found > 0).then(|| position_sum as f64 / found as f64)
+    }
+
+    /// Runs that got to the t-th treasuresphere
+    pub fn reached(&self, t: &SpherePos) -> u64 {
+        self.ts_counts[t.index()].iter().sum()
+    }
+
+    fn ratio(&self, count: u64) -> f64 {
+        match self.runs {
+            0 => 0.0,
+            runs => count as f64 / runs as f64,
+        }
+    }
+}
+
+/// Simulates `game_count` runs and collects their statistics
+pub fn simulate(game_count: &usize, settings: &RunSettings) -> Result<RunStats, Error> {
+    let loot_counts = loot::player_loot::loot_counts(settings.player_count);
+    simulate_runs(
+        game_count,
+        settings,
+        RunStats::default,
+        |mut acc, ts, it| {
+            acc.add(ts, it, &loot_counts);
+            acc
+        },
+        RunStats::merge,
+    )
+}
+
+/// Upper tail probability of a chi-square statistic with `df` degrees of freedom
+pub fn chi_square_p(statistic: f64, df: usize) -> f64 {
+    match (statistic, df) {
+        (_, 0) => 1.0,
+        (x, _) if x.is_infinite() => 0.0,
+        (x, df) => gamma_q(df as f64 / 2.0, x / 2.0),
+    }
+}
+
+/// Two-sided probability of a standard normal z-score at least this far from 0
+pub fn normal_p(z: f64) -> f64 {
+    if !z.is_finite() {
+        return 0.0;
+    }
+    gamma_q(0.5, z * z / 2.0)
+}
+
+/// Probability of a Kolmogorov-Smirnov distance at least `d` over `n` samples
+///
+/// Uses the asymptotic distribution with Stephens' correction for small `n`. For discrete
+/// distributions, like items, it's conservative.
+pub fn ks_p(d: f64, n: u64) -> f64 {
+    if n == 0 || d <= 0.0 {
+        return 1.0;
+    }
+    let sqrt_n = (n as f64).sqrt();
+    let lambda = (sqrt_n + 0.12 + 0.11 / sqrt_n) * d;
+    if lambda < 0.2 {
+        return 1.0;
+    }
+    let sum: f64 = (1..=100)
+        .map(|k| {
+            let k = k as f64;
+            let sign = if k % 2.0 == 1.0 { 1.0 } else { -1.0 };
+            sign * (-2.0 * k * k * lambda * lambda).exp()
+        })
+        .sum();
+    (2.0 * sum).clamp(0.0, 1.0)
+}
+
+/// Regularized upper incomplete gamma function Q(a, x)
+fn gamma_q(a: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 1.0;
+    }
+    let log_prefix = a * x.ln() - x - ln_gamma(a);
+    if x < a + 1.0 {
+        // Series for P(a, x)
+        let mut term = 1.0 / a;
+        let mut sum = term;
+        for n in 1..500 {
+            term *= x / (a + n as f64);
+            sum += term;
+            if term.abs() < sum.abs() * 1e-15 {
+                break;
+            }
+        }
+        (1.0 - sum * log_prefix.exp()).clamp(0.0, 1.0)
+    } else {
+        // Continued fraction for Q(a, x), modified Lentz
+        let tiny = 1e-300;
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / tiny;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        for n in 1..500 {
+            let an = -(n as f64) * (n as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            if d.abs() < tiny {
+                d = tiny;
+            }
+            c = b + an / c;
+            if c.abs() < tiny {
+                c = tiny;
+            }
+            d = 1.0 / d;
+            let delta = d * c;
+            h *= delta;
+            if (delta - 1.0).abs() < 1e-15 {
+                break;
+            }
+        }
+        (h * log_prefix.exp()).clamp(0.0, 1.0)
+    }
+}
+
+/// Natural log of the gamma function, Lanczos approximation
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.18009172947146,
+        -86.50532032941677,
+        24.01409824083091,
+        -1.231739572450155,
+        0.1208650973866179e-2,
+        -0.5395239384953e-5,
+    ];
+    let tmp = x + 5.5 - (x + 0.5) * (x + 5.5).ln();
+    let series: f64 = COEFFICIENTS
+        .iter()
+        .enumerate()
+        .map(|(j, c)| c / (x + 1.0 + j as f64))
+        .sum();
+    -tmp + (2.5066282746310005 * (1.000000000190015 + series) / x).ln()
+}
+
+// Slow statistical regression tests, run with `cargo test --release -- --ignored`.
+// The item reference comes from `rns-loot-sim sweep -n 200000 -s 20251121`, regenerate
+// it only when a change to the distribution is intended.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bag::Bag;
+    use crate::ids::PlayerCount;
+    use crate::rules::{ItemPools, Rules};
+    use crate::unlocks::{parse_item, Unlocks};
+
+    const REFERENCE: &str = include_str!("../tests/data/reference_sweep.csv");
+    const RUNS: usize = 100_000;
+    const SEED: Option<u64> = Some(1); // not the reference's, so the runs are independent
+    const SIGMAS: f64 = 5.0;
+
+    /// Checks `count / runs` is within `SIGMAS` standard errors of `expected`, taken from
+    /// `expected_runs` runs or exact if none
+    fn check(what: &str, count: u64, runs: u64, expected: f64, expected_runs: Option<u64>) {
+        let observed = count as f64 / runs as f64;
+        let p = (observed + expected) / 2.0;
+        let weight = 1.0 / runs as f64 + expected_runs.map_or(0.0, |x| 1.0 / x as f64);
+        let tolerance = SIGMAS * (p * (1.0 - p) * weight).sqrt() + 1e-9;
+        assert!(
+            (observed - expected).abs() <= tolerance,
+            "{}: {:.6} against {:.6} (tolerance {:.6})",
+            what,
+            observed,
+            expected,
+            tolerance
+        );
+    }
+
+    #[test]
+    fn p_values_match_the_tables() {
+        assert!((chi_square_p(3.841, 1) - 0.05).abs() < 1e-4);
+        assert!((chi_square_p(11.070, 5) - 0.05).abs() < 1e-4);
+        assert_eq!(chi_square_p(2.0, 0), 1.0);
+        assert_eq!(chi_square_p(f64::INFINITY, 3), 0.0);
+
+        assert!((normal_p(1.96) - 0.05).abs() < 1e-4);
+        assert!((normal_p(-2.576) - 0.01).abs() < 1e-4);
+        assert_eq!(normal_p(0.0), 1.0);
+        assert_eq!(normal_p(f64::NEG_INFINITY), 0.0);
+    }
+
+    #[test]
+    fn ks_p_matches_the_kolmogorov_distribution() {
+        // P(K > 1.3581) = 0.05 and P(K > 1) = 0.2700, with n large enough to leave out
+        // Stephens' correction
+        let n = 1_000_000;
+        let scale = (n as f64).sqrt() + 0.12 + 0.11 / (n as f64).sqrt();
+        assert!((ks_p(1.3581 / scale, n) - 0.05).abs() < 1e-4);
+        assert!((ks_p(1.0 / scale, n) - 0.2700).abs() < 1e-4);
+        assert_eq!(ks_p(0.0, n), 1.0);
+        assert_eq!(ks_p(0.5, 0), 1.0);
+    }
+
+    #[test]
+    fn gamma_q_matches_closed_forms() {
+        // Q(1, x) = e^-x, by the continued fraction
+        assert!((gamma_q(1.0, 2.0) - (-2.0f64).exp()).abs() < 1e-12);
+        assert!((gamma_q(1.0, 10.0) - (-10.0f64).exp()).abs() < 1e-12);
+        // Q(3, x) = e^-x (1 + x + x^2 / 2), by the series
+        let x: f64 = 2.5;
+        assert!((gamma_q(3.0, x) - (-x).exp() * (1.0 + x + x * x / 2.0)).abs() < 1e-12);
+        assert_eq!(gamma_q(2.0, 0.0), 1.0);
+    }
+
+    fn simulate_normal(player_count: PlayerCount) -> RunStats {
+        let pools = ItemPools::new(&Unlocks::all(), Rules::default());
+        simulate(&RUNS, &RunSettings::new(SEED, player_count, &pools)).unwrap()
+    }
+
+    #[test]
+    #[ignore = "slow, run with --ignored"]
+    fn sphere_frequencies_match_the_bag() {
+        for player_count in PlayerCount::all() {
+            let stats = simulate_normal(player_count);
+            for t in 0..*TS_COUNT {
+                for (c, color) in COLORS.iter().enumerate() {
+                    let bag = Bag::standard().spheres();
+                    let exact =
+                        bag.iter().filter(|x| *x == color).count() as f64 / bag.len() as f64;
+                    check(
+                        &format!("{}p ts_{} {}", player_count, t, color),
+                        stats.ts_counts[t][c],
+                        stats.runs,
+                        exact,
+                        None,
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[ignore = "slow, run with --ignored"]
+    fn item_frequencies_match_the_reference() {
+        let mut rdr = csv::Reader::from_reader(REFERENCE.as_bytes());
+        let headers = rdr.headers().unwrap().clone();
+        let records: Vec<csv::StringRecord> = rdr.records().map(|x| x.unwrap()).collect();
+        let column = |name: &str| headers.iter().position(|x| x == name).unwrap();
+        let field =
+            |row: &csv::StringRecord, name: &str| -> f64 { row[column(name)].parse().unwrap() };
+        let (player_column, item_column) = (column("player_count"), column("item"));
+        for player_count in PlayerCount::all() {
+            let stats = simulate_normal(player_count);
+            let rows = records
+                .iter()
+                .filter(|x| x[player_column].parse::<PlayerCount>().unwrap() == player_count);
+            let mut checked = 0;
+            for row in rows {
+                let item = parse_item(&row[item_column]).unwrap();
+                let reference_runs = field(row, "runs") as u64;
+                let name = format!("{}p {}", player_count, &row[item_column]);
+                check(
+                    &name,
+                    stats.item_found(&item),
+                    stats.runs,
+                    field(row, "probability"),
+                    Some(reference_runs),
+                );
+                for t in 0..*TS_COUNT {
+                    check(
+                        &format!("{} at ts_{}", name, t),
+                        stats.it_counts[item.index()][t],
+                        stats.runs,
+                        field(row, &format!("p_ts_{}", t)),
+                        Some(reference_runs),
+                    );
+                }
+                checked += 1;
+            }
+            assert_eq!(checked, *IT_COUNT);
+        }
+    }
+}
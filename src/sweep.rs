@@ -0,0 +1,119 @@
+// Module for sweeping the simulator over a grid of settings
+use crate::bag::Bag;
+use crate::compress::{self, Compression};
+use crate::ids::{ItemId, SpherePos};
+use crate::loot;
+use crate::rules::{ItemPools, Rules};
+use crate::stats;
+use crate::unlocks::Unlocks;
+use crate::{RunSettings, SimArgs};
+use anyhow::{Error, Result};
+use csv::Writer;
+use loot::TS_COUNT;
+
+#[derive(clap::Args, Debug)]
+pub struct SweepArgs {
+    #[command(flatten)]
+    sim: SimArgs,
+
+    /// Unlock profiles to sweep (`all` or files), joined by ','
+    #[arg(short, long, value_delimiter = ',', default_values_t = [String::from("all")])]
+    unlocks: Vec<String>,
+
+    /// Rules files to sweep, joined by ','. `none` sweeps without rules
+    #[arg(short, long, value_delimiter = ',', default_values_t = [String::from("none")])]
+    rules: Vec<String>,
+
+    /// Bag files with the count of each treasuresphere color to sweep, joined by ','.
+    /// `default` sweeps the game's bag
+    #[arg(short, long, value_delimiter = ',', default_values_t = [String::from("default")])]
+    bags: Vec<String>,
+
+    /// Output file (csv), if not used, print to stdout
+    #[arg(short, long)]
+    output_file: Option<String>,
+
+    /// Compress the output, picked by the file extension (.gz, .zst) if not used
+    #[arg(long, value_enum)]
+    compress: Option<Compression>,
+}
+
+/// Runs every combination of the grid and writes one row per combination and item
+pub fn run(args: &SweepArgs) -> Result<(), Error> {
+    let out = compress::create(&args.output_file, args.compress)?;
+    let mut wtr = Writer::from_writer(out);
+
+    let mut headers: Vec<String> = [
+        "player_count",
+        "unlocks",
+        "rules",
+        "bag",
+        "runs",
+        "item",
+        "probability",
+        "mean_position",
+    ]
+    .map(String::from)
+    .to_vec();
+    headers.extend((0..*TS_COUNT).map(|t| format!("p_ts_{}", t)));
+    wtr.write_record(&headers)?;
+
+    // Loaded once, the grid reuses them for every combination
+    let unlocks: Vec<Unlocks> = args
+        .unlocks
+        .iter()
+        .map(|x| Unlocks::load(x))
+        .collect::<Result<_, Error>>()?;
+    let rules: Vec<Rules> = args
+        .rules
+        .iter()
+        .map(|x| match x.as_str() {
+            "none" => Ok(Rules::default()),
+            path => Rules::from_file(path),
+        })
+        .collect::<Result<_, Error>>()?;
+    let bags: Vec<Bag> = args
+        .bags
+        .iter()
+        .map(|x| Bag::load(x))
+        .collect::<Result<_, Error>>()?;
+
+    let game_count = args.sim.run_count as usize;
+    for player_count in &args.sim.player_count {
+        for (u, unlocks_t) in unlocks.iter().enumerate() {
+            for (r, rules_r) in rules.iter().enumerate() {
+                let pools = ItemPools::new(unlocks_t, rules_r.clone());
+                for (b, bag_b) in bags.iter().enumerate() {
+                    let settings = RunSettings {
+                        bag: bag_b,
+                        ..RunSettings::new(args.sim.seed, *player_count, &pools)
+                    };
+                    let stats = stats::simulate(&game_count, &settings)?;
+
+                    for item in ItemId::all() {
+                        let mut record = vec![
+                            player_count.to_string(),
+                            args.unlocks[u].clone(),
+                            args.rules[r].clone(),
+                            args.bags[b].clone(),
+                            stats.runs.to_string(),
+                            item.to_string(),
+                            format!("{:.6}", stats.item_probability(&item)),
+                            stats
+                                .item_mean_position(&item)
+                                .map_or(String::new(), |x| format!("{:.4}", x)),
+                        ];
+                        record.extend(
+                            SpherePos::all()
+                                .map(|t| format!("{:.6}", stats.item_probability_at(&item, &t))),
+                        );
+                        wtr.write_record(&record)?;
+                    }
+                }
+            }
+        }
+    }
+
+    wtr.into_inner().map_err(|e| e.into_error())?.finish()?;
+    Ok(())
+}
@@ -0,0 +1,228 @@
+// Module for the terminal UI stepping through a run one treasuresphere at a time
+use crate::error::SimError;
+use crate::ids::{ItemId, PlayerCount, SpherePos};
+use crate::itemset::ItemSet;
+use crate::loot;
+use crate::rules::{ItemPools, Rules};
+use crate::{generate_ts, roll_sphere, run_rng, PoolArgs, SphereRoll};
+use anyhow::{Error, Result};
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::style::{Print, Stylize};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use loot::treasuresphere::{is_item_in_ts_pos, Colors as Treasuresphere};
+use loot::TS_COUNT;
+use rand_chacha::ChaCha8Rng;
+use std::io::{self, Write};
+
+#[derive(clap::Args, Debug)]
+pub struct TuiArgs {
+    /// Player count
+    #[arg(short, long, default_value = "1")]
+    player_count: PlayerCount,
+
+    #[command(flatten)]
+    pool: PoolArgs,
+
+    /// Seed to start from, a random one if not used
+    #[arg(short, long)]
+    seed: Option<u64>,
+
+    /// Run to start from, the row it has in a file written with the same seed
+    #[arg(long, default_value_t = 0)]
+    run: usize,
+}
+
+/// A run being stepped through, it rolls exactly like the generator
+struct Stepper {
+    seed: u64,
+    run: usize,
+    rng: ChaCha8Rng,
+    ts: Vec<Treasuresphere>,
+    loot_counts: Vec<usize>,
+    rolls: Vec<SphereRoll>,
+    items_found: Vec<ItemId>,
+    found: ItemSet,
+    error: Option<String>,
+}
+
+impl Stepper {
+    fn new(seed: u64, run: usize, loot_counts: &[usize]) -> Self {
+        let mut rng = run_rng(&Some(seed), &run);
+        let ts = generate_ts(&mut rng);
+        Stepper {
+            seed,
+            run,
+            rng,
+            ts,
+            loot_counts: loot_counts.to_vec(),
+            rolls: Vec::new(),
+            items_found: Vec::new(),
+            found: ItemSet::default(),
+            error: None,
+        }
+    }
+
+    /// Rolls the next treasuresphere, if any is left
+    fn next(&mut self, pools: &ItemPools) {
+        let Ok(t) = SpherePos::new(self.rolls.len()) else {
+            return;
+        };
+        if self.error.is_some() {
+            return;
+        }
+        let loot_count = self.loot_counts[t.index()];
+        let roll = roll_sphere(
+            &self.ts[t.index()],
+            &t,
+            loot_count + self.items_found.len(),
+            loot_count,
+            &self.found,
+            &mut self.rng,
+            pools,
+        );
+        if roll.chosen().len() < loot_count {
+            let error = SimError::PoolExhausted {
+                color: self.ts[t.index()],
+                position: t,
+            };
+            self.error = Some(error.to_string());
+        }
+        self.items_found.extend(roll.chosen());
+        self.found.extend(roll.chosen());
+        self.rolls.push(roll);
+    }
+
+    /// Lines describing the run so far
+    fn lines(&self, rules: &Rules) -> Vec<String> {
+        let mut lines = vec![
+            format!(
+                "seed {}, run {}: {} / {} treasurespheres",
+                self.seed,
+                self.run,
+                self.rolls.len(),
+                TS_COUNT
+            ),
+            String::new(),
+        ];
+
+        let mut found_before: Vec<ItemId> = Vec::new();
+        for (t, roll) in SpherePos::all().zip(&self.rolls) {
+            lines.push(format!(
+                "ts_{} {}: {} of {} items shuffled in, {} to find",
+                t,
+                self.ts[t.index()].to_string().bold(),
+                roll.candidates().len(),
+                roll.pool_size(),
+                self.loot_counts[t.index()]
+            ));
+            for (p, item) in roll.candidates().iter().enumerate() {
+                let line = if roll.chosen().contains(item) {
+                    format!("  + {}", item).green().to_string()
+                } else if p >= roll.looked {
+                    format!("    {} (not reached)", item).dim().to_string()
+                } else if found_before.contains(item) {
+                    format!("  - {} (already found)", item).red().to_string()
+                } else if !is_item_in_ts_pos(item, &t) {
+                    format!("  - {} (not in the last treasurespheres)", item)
+                        .red()
+                        .to_string()
+                } else if !rules.is_item_in_ts_pos(item, &t) {
+                    format!("  - {} (position limit in the rules)", item)
+                        .red()
+                        .to_string()
+                } else {
+                    format!("  - {}", item).red().to_string()
+                };
+                lines.push(line);
+            }
+            found_before.extend(roll.chosen());
+        }
+
+        if let Some(error) = &self.error {
+            lines.push(error.clone().red().to_string());
+        } else if self.rolls.len() == *TS_COUNT {
+            let mut items: Vec<String> = self.items_found.iter().map(|x| x.to_string()).collect();
+            items.sort_unstable();
+            lines.push(String::new());
+            lines.push(format!("found: {}", items.join(", ")));
+        }
+        lines
+    }
+}
+
+/// Raw mode and the alternate screen, left when dropped, so a panic restores the terminal
+struct Terminal;
+
+impl Terminal {
+    fn enter() -> Result<Self, Error> {
+        terminal::enable_raw_mode()?;
+        let terminal = Terminal;
+        execute!(io::stdout(), EnterAlternateScreen, Hide)?;
+        Ok(terminal)
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        // Nothing left to report errors to
+        let _ = execute!(io::stdout(), Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Runs the terminal UI until `q` or escape is pressed
+pub fn run(args: &TuiArgs) -> Result<(), Error> {
+    let pools = args.pool.load()?;
+    let player_count = args.player_count;
+    let loot_counts = loot::player_loot::loot_counts(player_count);
+    let mut stepper = Stepper::new(
+        args.seed.unwrap_or_else(rand::random),
+        args.run,
+        &loot_counts,
+    );
+
+    let mut out = io::stdout();
+    let _terminal = Terminal::enter()?;
+    let mut scroll = 0;
+    loop {
+        draw(&mut out, &stepper.lines(pools.rules()), scroll)?;
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+            KeyCode::Char('n') | KeyCode::Char(' ') | KeyCode::Right | KeyCode::Enter => {
+                stepper.next(&pools)
+            }
+            KeyCode::Char('r') => {
+                stepper = Stepper::new(stepper.seed, stepper.run + 1, &loot_counts);
+                scroll = 0;
+            }
+            KeyCode::Char('s') => {
+                stepper = Stepper::new(rand::random(), 0, &loot_counts);
+                scroll = 0;
+            }
+            KeyCode::Down | KeyCode::Char('j') => scroll += 1,
+            KeyCode::Up | KeyCode::Char('k') => scroll = scroll.saturating_sub(1),
+            _ => {}
+        }
+    }
+}
+
+fn draw(out: &mut impl Write, lines: &[String], scroll: usize) -> Result<(), Error> {
+    let (_, height) = terminal::size()?;
+    let help = "[n] next treasuresphere  [r] reroll run  [s] new seed  [up/down] scroll  [q] quit";
+    queue!(out, Clear(ClearType::All), MoveTo(0, 0))?;
+    let shown = (height as usize).saturating_sub(2);
+    for (row, line) in lines.iter().skip(scroll).take(shown).enumerate() {
+        queue!(out, MoveTo(0, row as u16), Print(line))?;
+    }
+    queue!(out, MoveTo(0, height.saturating_sub(1)), Print(help.dim()))?;
+    out.flush()?;
+    Ok(())
+}
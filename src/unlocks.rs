@@ -0,0 +1,131 @@
+// Module for unlock profiles, i.e. which items a player can find at all
+use crate::ids::ItemId;
+use crate::loot;
+use anyhow::{bail, Context, Error, Result};
+use loot::treasuresphere::ITEM_NAMES;
+use loot::IT_COUNT;
+use std::fs;
+
+/// Items unlocked for a player, every treasuresphere pool is filtered through this
+#[derive(Debug, Clone)]
+pub struct Unlocks {
+    unlocked: Vec<bool>, // indexed by item index
+}
+
+impl Unlocks {
+    /// Every item unlocked, i.e. the game as a completionist sees it
+    pub fn all() -> Self {
+        Unlocks {
+            unlocked: vec![true; *IT_COUNT],
+        }
+    }
+
+    /// Loads every item for `all`, otherwise treats `profile` as a file path
+    pub fn load(profile: &str) -> Result<Self, Error> {
+        match profile {
+            "all" => Ok(Unlocks::all()),
+            path => Unlocks::from_file(path),
+        }
+    }
+
+    /// Reads a file listing unlocked items
+    ///
+    /// One item per line, either its name (it_[NAME]) or index.
+    /// Blank lines and anything after `#` are ignored.
+    pub fn from_file(path: &str) -> Result<Self, Error> {
+        let text = fs::read_to_string(path).with_context(|| {
+            format!(
+                "Could not read unlock profile '{}' (`all` or a file, there are no presets)",
+                path
+            )
+        })?;
+
+        let mut unlocked = vec![false; *IT_COUNT];
+        for (line_no, line) in text.lines().enumerate() {
+            let entry = line.split('#').next().unwrap_or("").trim();
+            if entry.is_empty() {
+                continue;
+            }
+            let item = parse_item(entry)
+                .with_context(|| format!("{}:{}: invalid unlock entry", path, line_no + 1))?;
+            unlocked[item.index()] = true;
+        }
+
+        Ok(Unlocks { unlocked })
+    }
+
+    pub fn is_unlocked(&self, item: &ItemId) -> bool {
+        self.unlocked[item.index()]
+    }
+}
+
+/// Parses an item given by name (it_[NAME]) or by index
+pub fn parse_item(entry: &str) -> Result<ItemId, Error> {
+    if let Ok(index) = entry.parse::<usize>() {
+        return Ok(ItemId::new(index)?);
+    }
+    match ITEM_NAMES.get_index(entry) {
+        Some(index) => Ok(ItemId::new(index)?),
+        None => bail!("Unknown item: {}", entry),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Writes `text` to a file of its own in the temp directory
+    fn profile_file(name: &str, text: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("rns-loot-sim-{}-{}.txt", name, std::process::id()));
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn parse_item_takes_names_and_indices() {
+        let item = parse_item("it_raven_grimoire").unwrap();
+        assert_eq!(item.name(), "it_raven_grimoire");
+        assert_eq!(parse_item(&item.index().to_string()).unwrap(), item);
+    }
+
+    #[test]
+    fn parse_item_rejects_unknown_items() {
+        assert!(parse_item("it_no_such_item").is_err());
+        assert!(parse_item(&IT_COUNT.to_string()).is_err());
+        assert!(parse_item("").is_err());
+    }
+
+    #[test]
+    fn from_file_unlocks_only_the_listed_items() {
+        let path = profile_file(
+            "listed",
+            "# a save file\n\nit_topaz_charm  # by name\n  1\n",
+        );
+        let unlocks = Unlocks::from_file(path.to_str().unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let mut expected = vec![
+            parse_item("it_topaz_charm").unwrap(),
+            ItemId::new(1).unwrap(),
+        ];
+        expected.sort_unstable();
+        let unlocked: Vec<ItemId> = ItemId::all().filter(|x| unlocks.is_unlocked(x)).collect();
+        assert_eq!(unlocked, expected);
+    }
+
+    #[test]
+    fn from_file_reports_the_line_of_a_bad_entry() {
+        let path = profile_file("bad", "it_raven_grimoire\nit_no_such_item\n");
+        let error = Unlocks::from_file(path.to_str().unwrap()).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert!(format!("{:#}", error).contains(":2: invalid unlock entry"));
+    }
+
+    #[test]
+    fn load_reads_all_or_a_file() {
+        assert!(ItemId::all().all(|x| Unlocks::load("all").unwrap().is_unlocked(&x)));
+        assert!(Unlocks::load("no/such/profile.txt").is_err());
+    }
+}
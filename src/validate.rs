@@ -0,0 +1,373 @@
+// Module for checking csv files in the writer.rs layout against the game rules
+use crate::bag::Bag;
+use crate::ids::{ItemId, PlayerCount, SpherePos};
+use crate::loot;
+use crate::reader::{self, Layout};
+use crate::unlocks::parse_item;
+use anyhow::{bail, Error, Result};
+use csv::StringRecord;
+use loot::treasuresphere::{is_item_in_ts_pos, Colors as Treasuresphere};
+use loot::TS_COUNT;
+
+#[derive(clap::Args, Debug)]
+pub struct ValidateArgs {
+    /// Csv files to check
+    #[arg(required = true)]
+    files: Vec<String>,
+}
+
+/// A rule broken in a cell of the file
+struct Issue {
+    row: usize, // 1 is the first row after the headers, 0 the headers
+    column: String,
+    message: String,
+}
+
+/// Checks every row of every file and prints what's wrong, by line and column
+pub fn run(args: &ValidateArgs) -> Result<(), Error> {
+    let mut issue_count = 0;
+    for path in &args.files {
+        let issues = validate_file(path)?;
+        for issue in &issues {
+            println!(
+                "{}: row {}, {}: {}",
+                path, issue.row, issue.column, issue.message
+            );
+        }
+        eprintln!("{}: {} issue(s)", path, issues.len());
+        issue_count += issues.len();
+    }
+
+    if issue_count > 0 {
+        bail!("{} issue(s) found", issue_count);
+    }
+    Ok(())
+}
+
+fn validate_file(path: &str) -> Result<Vec<Issue>, Error> {
+    // Ragged rows are an issue of their own, their cells are still checked
+    let mut rdr = reader::open_flexible(path)?;
+    let headers = rdr.headers()?.clone();
+    let layout = Layout::from_headers(&headers)?;
+
+    // Missing columns are reported once, their cells read as empty
+    let mut issues = Vec::new();
+    let mut missing = |column: String| {
+        issues.push(Issue {
+            row: 0,
+            column,
+            message: "missing column".to_string(),
+        })
+    };
+    for (t, c) in layout.ts.iter().enumerate() {
+        if c.is_none() {
+            missing(format!("ts_{}", t));
+        }
+    }
+    for (t, columns) in layout.it.iter().enumerate() {
+        for (i, c) in columns.iter().enumerate() {
+            if c.is_none() {
+                missing(format!("it_{}_{}", t, i));
+            }
+        }
+    }
+
+    for (row, record) in rdr.records().enumerate() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) if e.is_io_error() => return Err(e.into()),
+            Err(e) => {
+                issues.push(Issue {
+                    row: row + 1,
+                    column: "*".to_string(),
+                    message: e.to_string(),
+                });
+                continue;
+            }
+        };
+        if record.len() != headers.len() {
+            issues.push(Issue {
+                row: row + 1,
+                column: "*".to_string(),
+                message: format!("{} fields, but {} headers", record.len(), headers.len()),
+            });
+        }
+        issues.extend(validate_record(&layout, &record, row + 1));
+    }
+    Ok(issues)
+}
+
+fn validate_record(layout: &Layout, record: &StringRecord, row: usize) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    let mut issue = |column: String, message: String| {
+        issues.push(Issue {
+            row,
+            column,
+            message,
+        })
+    };
+
+    let player_count = match layout
+        .cell(record, &Some(layout.player_count))
+        .parse::<PlayerCount>()
+    {
+        Ok(p) => Some(p),
+        _ => {
+            issue(
+                "player_count".to_string(),
+                "expected a number from 1 to 4".to_string(),
+            );
+            None
+        }
+    };
+    let loot_counts = player_count.map(loot::player_loot::loot_counts);
+
+    // Treasurespheres, drawn without replacement from the bag of 8
+    let mut ts: Vec<Option<Treasuresphere>> = Vec::with_capacity(*TS_COUNT);
+    for (t, c) in layout.ts.iter().enumerate() {
+        let cell = layout.cell(record, c);
+        match cell.parse::<Treasuresphere>() {
+            Ok(color) => {
+                let seen = ts.iter().filter(|x| **x == Some(color)).count();
+                let in_bag = Bag::standard()
+                    .spheres()
+                    .iter()
+                    .filter(|x| **x == color)
+                    .count();
+                if seen >= in_bag {
+                    issue(
+                        format!("ts_{}", t),
+                        format!("{} drops at most {} time(s) a game", color, in_bag),
+                    );
+                }
+                ts.push(Some(color));
+            }
+            Err(_) => {
+                if c.is_some() {
+                    issue(
+                        format!("ts_{}", t),
+                        format!("unknown treasuresphere '{}'", cell),
+                    );
+                }
+                ts.push(None);
+            }
+        }
+    }
+
+    // Items per treasuresphere
+    let mut found: Vec<ItemId> = Vec::new();
+    for (t, columns) in SpherePos::all().zip(&layout.it) {
+        let mut count = 0;
+        let mut gap = false;
+        for (i, c) in columns.iter().enumerate() {
+            let column = format!("it_{}_{}", t, i);
+            let cell = layout.cell(record, c);
+            if cell.is_empty() {
+                gap = true;
+                continue;
+            }
+            count += 1;
+            if gap {
+                issue(column.clone(), "item after an empty cell".to_string());
+            }
+
+            let Ok(item) = parse_item(cell) else {
+                issue(column, format!("unknown item '{}'", cell));
+                continue;
+            };
+            let name = item.name();
+            if found.contains(&item) {
+                issue(
+                    column.clone(),
+                    format!("{} was already found this run", name),
+                );
+            }
+            found.push(item);
+            if let Some(color) = ts[t.index()]
+                && !color.pool().contains(&item)
+            {
+                issue(
+                    column.clone(),
+                    format!("{} isn't in the {} pool", name, color),
+                );
+            }
+            if !is_item_in_ts_pos(&item, &t) {
+                issue(
+                    column,
+                    format!("{} can't drop in the last treasurespheres", name),
+                );
+            }
+        }
+
+        if let Some(expected) = loot_counts.as_ref().map(|x| x[t.index()])
+            && count != expected
+        {
+            issue(
+                format!("it_{}_*", t),
+                format!(
+                    "{} items, but {} drop for {} player(s)",
+                    count,
+                    expected,
+                    player_count.map_or(0, |p| p.get())
+                ),
+            );
+        }
+    }
+
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const HEADERS: &str = "player_count,ts_0,ts_1,ts_2,ts_3,ts_4,ts_5,it_0_0,it_0_1,it_0_2,it_0_3,it_0_4,it_1_0,it_1_1,it_1_2,it_1_3,it_1_4,it_2_0,it_2_1,it_2_2,it_2_3,it_2_4,it_3_0,it_3_1,it_3_2,it_3_3,it_3_4,it_4_0,it_4_1,it_4_2,it_4_3,it_4_4,it_5_0,it_5_1,it_5_2,it_5_3,it_5_4";
+    // A 2 player run from the s20251121_p2_n100 golden file
+    const ROW: &str = "2,emerald,opal,normal,sapphire,ruby,normal,it_sunflower_crown,it_mermaid_scale,it_smoke_shield,it_falconfeather_dagger,it_stoneplate_armor,it_opal_necklace,it_nightingale_gown,it_sawtooth_cleaver,it_nova_crown,it_cursed_candlestaff,it_stonebreaker_staff,it_emerald_chestplate,it_firststrike_bracelet,it_crane_katana,,it_throwing_dagger,it_snipers_eyeglasses,it_battlemaiden_armor,it_blackbolt_ribbon,,it_greysteel_shield,it_flame_bow,it_spiked_shield,it_lion_charm,,it_purification_rod,it_old_bonnet,it_grandmaster_spear,it_staticshock_earrings,";
+
+    /// Issues of a file with `headers` and `rows`, as (row, column, message)
+    fn issues(name: &str, headers: &str, rows: &[String]) -> Vec<(usize, String, String)> {
+        let path =
+            std::env::temp_dir().join(format!("rns-loot-sim-{}-{}.txt", name, std::process::id()));
+        fs::write(&path, format!("{}\n{}\n", headers, rows.join("\n"))).unwrap();
+        let issues = validate_file(path.to_str().unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        issues
+            .into_iter()
+            .map(|x| (x.row, x.column, x.message))
+            .collect()
+    }
+
+    /// ROW with the cells of `changes` replaced, by column name
+    fn row_with(changes: &[(&str, &str)]) -> String {
+        let columns: Vec<&str> = HEADERS.split(',').collect();
+        let mut cells: Vec<&str> = ROW.split(',').collect();
+        for (column, value) in changes {
+            cells[columns.iter().position(|x| x == column).unwrap()] = value;
+        }
+        cells.join(",")
+    }
+
+    /// An item of `color`'s pool that isn't in ROW, and passes `keep`
+    fn unused_item(color: Treasuresphere, keep: impl Fn(&ItemId) -> bool) -> &'static str {
+        color
+            .pool()
+            .iter()
+            .find(|x| keep(x) && !ROW.split(',').any(|cell| cell == x.name()))
+            .unwrap()
+            .name()
+    }
+
+    fn single(name: &str, row: String) -> (usize, String, String) {
+        let mut found = issues(name, HEADERS, &[ROW.to_string(), row]);
+        assert_eq!(found.len(), 1, "{:?}", found);
+        found.remove(0)
+    }
+
+    #[test]
+    fn valid_rows_have_no_issues() {
+        assert!(issues("valid", HEADERS, &[ROW.to_string()]).is_empty());
+    }
+
+    #[test]
+    fn missing_columns_are_reported_once() {
+        let headers = HEADERS.replace(",ts_5,", ",ts_x,");
+        let found = issues("missing", &headers, &[ROW.to_string()]);
+        assert_eq!(
+            found,
+            vec![(0, "ts_5".to_string(), "missing column".to_string())]
+        );
+    }
+
+    #[test]
+    fn ragged_rows_are_reported_and_checked() {
+        let found = issues("ragged", HEADERS, &[format!("{},", ROW), ROW.to_string()]);
+        assert_eq!(
+            found,
+            vec![(1, "*".to_string(), "38 fields, but 37 headers".to_string())]
+        );
+
+        // The missing cells read as empty
+        let short = ROW.rsplitn(3, ',').last().unwrap().to_string();
+        let found = issues("short", HEADERS, &[short]);
+        assert_eq!(
+            found[0],
+            (1, "*".to_string(), "35 fields, but 37 headers".to_string())
+        );
+        assert_eq!(found[1].1, "it_5_*");
+        assert_eq!(found.len(), 2);
+    }
+
+    #[test]
+    fn bad_player_counts_and_spheres_are_reported() {
+        let (row, column, message) = single("players", row_with(&[("player_count", "5")]));
+        assert_eq!((row, column.as_str()), (2, "player_count"));
+        assert_eq!(message, "expected a number from 1 to 4");
+
+        let (_, column, message) = single("unknown-ts", row_with(&[("ts_1", "amber")]));
+        assert_eq!(
+            (column.as_str(), message.as_str()),
+            ("ts_1", "unknown treasuresphere 'amber'")
+        );
+
+        // The bag holds one Emerald
+        let found = issues("twice-ts", HEADERS, &[row_with(&[("ts_1", "emerald")])]);
+        assert!(found.contains(&(
+            1,
+            "ts_1".to_string(),
+            "emerald drops at most 1 time(s) a game".to_string()
+        )));
+    }
+
+    #[test]
+    fn bad_items_are_reported() {
+        let (_, column, message) = single("unknown-it", row_with(&[("it_0_0", "it_amber")]));
+        assert_eq!(
+            (column.as_str(), message.as_str()),
+            ("it_0_0", "unknown item 'it_amber'")
+        );
+
+        let (_, column, message) =
+            single("found-twice", row_with(&[("it_1_1", "it_opal_necklace")]));
+        assert_eq!(column, "it_1_1");
+        assert_eq!(message, "it_opal_necklace was already found this run");
+
+        let other = unused_item(Treasuresphere::Opal, |x| {
+            !Treasuresphere::Emerald.pool().contains(x)
+        });
+        let (_, column, message) = single("pool", row_with(&[("it_0_0", other)]));
+        assert_eq!(column, "it_0_0");
+        assert_eq!(message, format!("{} isn't in the emerald pool", other));
+
+        let last = SpherePos::new(5).unwrap();
+        let early = unused_item(Treasuresphere::Normal, |x| !is_item_in_ts_pos(x, &last));
+        let (_, column, message) = single("last", row_with(&[("it_5_0", early)]));
+        assert_eq!(column, "it_5_0");
+        assert_eq!(
+            message,
+            format!("{} can't drop in the last treasurespheres", early)
+        );
+    }
+
+    #[test]
+    fn gaps_and_counts_are_reported() {
+        let found = issues(
+            "gap",
+            HEADERS,
+            &[row_with(&[("it_2_3", ""), ("it_2_4", "it_crane_katana")])],
+        );
+        assert_eq!(
+            found,
+            vec![(
+                1,
+                "it_2_4".to_string(),
+                "item after an empty cell".to_string()
+            )]
+        );
+
+        let (_, column, message) = single("count", row_with(&[("it_3_3", "")]));
+        assert_eq!(column, "it_3_*");
+        assert_eq!(message, "3 items, but 4 drop for 2 player(s)");
+    }
+}
@@ -1,19 +1,39 @@
 // Module for writer functions
+use crate::error::SimError;
+use crate::ids::{ItemId, PlayerCount};
 use crate::loot;
-use anyhow::{Error, Result};
 use csv::Writer;
 use loot::treasuresphere::Colors as Treasuresphere;
 use loot::TS_COUNT; // vanilla constants for item count and ts count in 1.4.5
+use std::io::Write;
+
+/// Formats runs can be written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    Csv,
+    Binary,
+}
+
+impl std::fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            OutputFormat::Csv => "csv",
+            OutputFormat::Binary => "binary",
+        };
+        f.write_str(name)
+    }
+}
 
 /// Writes the headers for our CSV file
 //
 // I've included an unused "relative" bool where for 1-3p
 // Where in 1p, this excludes the headers  {2..=5}_{3,4}
 pub fn field_wtr_headers(
-    wtr: &mut Writer<Vec<u8>>,
+    wtr: &mut Writer<impl Write>,
     _relative: &bool,
-    _player_count: &usize,
-) -> Result<(), Error> {
+    _player_count: &PlayerCount,
+    loadouts: &bool,
+) -> Result<(), SimError> {
     // Writes the ts_headers
     wtr.write_field("player_count")?;
     for t in 0..*TS_COUNT {
@@ -23,17 +43,7 @@ pub fn field_wtr_headers(
 
     // Writes the it_headers
     if *_relative {
-        todo!("Relative flag not priority.");
-        // for t in 0..*TS_COUNT {
-        //     let loot_counts = loot::player_loot::loot_counts(*_player_count as usize)?;
-        //     let loot_count = loot_counts
-        //         .get(t)
-        //         .expect("ts indexing exceeded bounds of loot_counts in field_wtr_headers().");
-        //     for i in 0..*loot_count {
-        //         let it_t_i: String = format!("it_{}_{}", t, i);
-        //         wtr.write_field(it_t_i)?;
-        //     }
-        // }
+        return Err(SimError::RelativeColumns); // Relative flag not priority
     } else {
         for t in 0..*TS_COUNT {
             for i in 0..*loot::IT_FOUND_MAX_PER_TS {
@@ -42,47 +52,52 @@ pub fn field_wtr_headers(
             }
         }
     };
+
+    // Writes the loadout headers, always all 4 players like the it_headers
+    if *loadouts {
+        for p in 0..PlayerCount::MAX {
+            wtr.write_field(format!("loadout_{}", p))?;
+        }
+    }
     wtr.write_record(None::<&[u8]>)?;
 
     Ok(())
 }
 
 pub fn field_wtr(
-    wtr: &mut Writer<Vec<u8>>,
-    treasurespheres: &Vec<Treasuresphere>,
-    loot: &Vec<usize>,
+    wtr: &mut Writer<impl Write>,
+    treasurespheres: &[Treasuresphere],
+    loot: &[ItemId],
     _relative: &bool,
-    player_count: &usize,
-) -> Result<(), Error> {
-    let loot_counts = loot::player_loot::loot_counts(*player_count as usize)?;
+    player_count: &PlayerCount,
+    loadouts: Option<&[Vec<ItemId>]>,
+) -> Result<(), SimError> {
+    let loot_counts = loot::player_loot::loot_table(*player_count);
 
     if *_relative {
-        todo!("Relative flag not priority.");
+        return Err(SimError::RelativeColumns); // Relative flag not priority
     } else {
         wtr.write_field(player_count.to_string())?;
         for t in 0..*TS_COUNT {
-            let ts = treasurespheres
-                .get(t)
-                .expect("ts index exceeded the bounds of rolled treasurespheres in field_wtr().");
-            wtr.write_field(ts.to_string())?;
+            let ts = treasurespheres.get(t).ok_or(SimError::IndexOutOfRange {
+                what: "treasuresphere",
+                index: t,
+                len: treasurespheres.len(),
+            })?;
+            wtr.write_field(ts.name())?;
         }
 
         let mut loot_index = 0;
-        for t in 0..*TS_COUNT {
+        for loot_count in loot_counts.iter() {
             for i in 0..*loot::IT_FOUND_MAX_PER_TS {
-                let loot_count = loot_counts
-                    .get(t)
-                    .expect("ts indexing exceeded bounds of loot_counts in field_wtr().");
-
                 let item = if i < *loot_count {
-                    let it = *loot
-                        .get(loot_index)
-                        .expect("Item index exceeded bounds of loot in field_wtr().")
-                        as usize;
+                    let it = *loot.get(loot_index).ok_or(SimError::IndexOutOfRange {
+                        what: "loot",
+                        index: loot_index,
+                        len: loot.len(),
+                    })?;
                     loot_index += 1;
-                    loot::treasuresphere::ITEM_NAMES
-                        .index(it)
-                        .expect("Item not found in index for ITEM_NAMES in field_wtr().")
+                    it.name()
                 } else {
                     ""
                 }; // Write nothing i.e. for it_{2..=5}_{3,4}
@@ -92,6 +107,21 @@ pub fn field_wtr(
         }
     };
 
+    // Items per player joined by ';', nothing for players not in the game
+    if let Some(loadouts) = loadouts {
+        for p in 0..PlayerCount::MAX {
+            let loadout = match loadouts.get(p) {
+                Some(items) => items
+                    .iter()
+                    .map(|it| it.name())
+                    .collect::<Vec<&str>>()
+                    .join(";"),
+                None => String::new(),
+            };
+            wtr.write_field(loadout)?;
+        }
+    }
+
     wtr.write_record(None::<&[u8]>)?;
     Ok(())
 }
@@ -0,0 +1,67 @@
+// Golden outputs pinning what seeds cited in community posts generate
+//
+// Each case is compared byte for byte with tests/golden/v<generation version>/<name>.csv.
+// When a change to the generator is intended, bump GENERATION_VERSION and write the new
+// files with `UPDATE_GOLDEN=1 cargo test --test golden`.
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+const BIN: &str = env!("CARGO_BIN_EXE_rns-loot-sim");
+
+/// Name of the golden file and the arguments generating it
+const CASES: &[(&str, &[&str])] = &[
+    ("s5_p1_n50", &["-s", "5", "-p", "1", "-n", "50"]),
+    ("s5_p4_n20", &["-s", "5", "-p", "4", "-n", "20"]),
+    ("s20251121_p2_n100", &["-s", "20251121", "-p", "2", "-n", "100"]),
+    ("s42_p3_n30", &["-s", "42", "-p", "3", "-n", "30"]),
+    ("s7_mix_n40", &["-s", "7", "-p", "1:2,4", "-n", "40"]),
+    ("s9_p4_n20_draft", &["-s", "9", "-p", "4", "-n", "20", "--draft", "random"]),
+];
+
+/// Generation algorithm version, as shown by `--version`
+fn generation_version() -> String {
+    let output = Command::new(BIN).arg("--version").output().unwrap();
+    let version = String::from_utf8(output.stdout).unwrap();
+    version
+        .trim()
+        .trim_end_matches(')')
+        .rsplit(' ')
+        .next()
+        .unwrap()
+        .to_string()
+}
+
+#[test]
+fn seeds_generate_their_golden_output() {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("v{}", generation_version()));
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+    if update {
+        fs::create_dir_all(&dir).unwrap();
+    }
+
+    for (name, args) in CASES {
+        let output = Command::new(BIN).args(*args).output().unwrap();
+        assert!(output.status.success(), "{}: {:?}", name, output);
+
+        let path = dir.join(format!("{}.csv", name));
+        if update {
+            fs::write(&path, &output.stdout).unwrap();
+            continue;
+        }
+        let golden = fs::read(&path).unwrap_or_else(|_| {
+            panic!(
+                "{} is missing, write it with UPDATE_GOLDEN=1 if the generation version was bumped",
+                path.display()
+            )
+        });
+        assert!(
+            output.stdout == golden,
+            "{}: the output of `{}` changed, seeds must keep generating the same runs",
+            name,
+            args.join(" ")
+        );
+    }
+}